@@ -0,0 +1,294 @@
+use crate::geometries::mesh::Mesh;
+use crate::objects::MeshShader;
+use crate::object::*;
+use crate::buffer::*;
+use crate::core::Gl;
+
+///
+/// The decoded PBR material of a glTF primitive: the metallic-roughness factors together with the
+/// raw, already-decoded base-color/metallic-roughness/normal/emissive images (if the material
+/// referenced them).
+///
+/// Scope note: [Mesh::new_from_gltf_source] attaches the default, untextured [MeshShader] to every
+/// [Object] - the same placeholder the OBJ importer uses - and returns the decoded [GltfMaterial]s
+/// alongside it rather than binding their textures into the object itself. Building and attaching
+/// a textured PBR [MeshShader] (or other material) from these images is left to the caller; this
+/// importer only covers decoding the glTF document and images, not wiring them into rendering.
+///
+#[derive(Debug, Default)]
+pub struct GltfMaterial {
+    /// The base color factor, multiplied with the base color texture (if any).
+    pub base_color_factor: [f32; 4],
+    /// The metallic factor, multiplied with the metallic-roughness texture's blue channel (if any).
+    pub metallic_factor: f32,
+    /// The roughness factor, multiplied with the metallic-roughness texture's green channel (if any).
+    pub roughness_factor: f32,
+    /// Decoded base color image pixels, width, height. Pixels of every texture field keep the
+    /// source image's own channel layout ([gltf::image::Format]); they are not converted.
+    pub base_color_texture: Option<(Vec<u8>, u32, u32)>,
+    /// Decoded metallic(B)-roughness(G) image pixels, width, height.
+    pub metallic_roughness_texture: Option<(Vec<u8>, u32, u32)>,
+    /// Decoded tangent-space normal image pixels, width, height.
+    pub normal_texture: Option<(Vec<u8>, u32, u32)>,
+    /// Decoded emissive image pixels, width, height.
+    pub emissive_texture: Option<(Vec<u8>, u32, u32)>,
+}
+
+///
+/// A punctual light (directional, point or spot) imported from a glTF node, with its
+/// position/direction already transformed into world space by the node hierarchy. The concrete
+/// [crate::light] type to construct from this depends on `kind`.
+///
+#[derive(Debug, Clone)]
+pub struct GltfLight {
+    /// Which kind of light this is, and the extra parameters that kind needs.
+    pub kind: GltfLightKind,
+    /// The light's world space position (meaningful for [GltfLightKind::Point]/[GltfLightKind::Spot]).
+    pub position: Vec3,
+    /// The direction the light shines in, i.e. the node's local -Z axis in world space
+    /// (meaningful for [GltfLightKind::Directional]/[GltfLightKind::Spot]).
+    pub direction: Vec3,
+    /// The light's linear color.
+    pub color: Vec3,
+    /// The light's intensity, in the unit glTF specifies for that light kind (lux for directional,
+    /// candela for point/spot).
+    pub intensity: f32,
+}
+
+/// The kind-specific parameters of a [GltfLight].
+#[derive(Debug, Clone, Copy)]
+pub enum GltfLightKind {
+    /// A light that shines uniformly in `direction`.
+    Directional,
+    /// A light that shines from `position` in every direction.
+    Point,
+    /// A light that shines from `position` in `direction`, restricted to a cone of the given
+    /// half-angle, in radians.
+    Spot {
+        /// The half-angle, in radians, where the light's intensity starts to fall off.
+        inner_cone_angle: f32,
+        /// The half-angle, in radians, beyond which the light contributes nothing.
+        outer_cone_angle: f32,
+    },
+}
+
+impl Mesh {
+    ///
+    /// Parses a glTF 2.0 asset, mirroring [Mesh::new_from_obj_source] but additionally importing
+    /// materials, the node transform hierarchy and punctual lights. Accepts either a `.gltf` JSON
+    /// document whose buffers/images are embedded as base64 data URIs, or a self-contained `.glb`
+    /// binary - [gltf::Gltf::from_slice] auto-detects which. No base path is passed to the
+    /// importer, so buffer/image URIs that refer to files relative to the asset are not resolved
+    /// and make the import fail with [GltfError::Parse]. Returns one [Object] per glTF mesh (transformed by its accumulated node transform),
+    /// its per-primitive [GltfMaterial]s, and every punctual light found in the scene.
+    ///
+    /// Each returned [Object] carries the default, untextured [MeshShader] - the decoded
+    /// [GltfMaterial] textures are handed back alongside it, not bound into the object, so
+    /// attaching them to a textured material is the caller's responsibility (see [GltfMaterial]).
+    ///
+    pub fn new_from_gltf_source(
+        gl: &Gl,
+        source: &[u8],
+    ) -> Result<(Vec<Object>, Vec<Vec<GltfMaterial>>, Vec<GltfLight>), GltfError> {
+        let gltf::Gltf { document, blob } =
+            gltf::Gltf::from_slice(source).map_err(|e| GltfError::Parse(e.to_string()))?;
+        let buffers = gltf::import_buffers(&document, None, blob)
+            .map_err(|e| GltfError::Parse(e.to_string()))?;
+        let images = gltf::import_images(&document, None, &buffers)
+            .map_err(|e| GltfError::Parse(e.to_string()))?;
+
+        let scene = document
+            .default_scene()
+            .or_else(|| document.scenes().next())
+            .ok_or_else(|| GltfError::Parse("glTF asset has no scene".to_string()))?;
+
+        let mut objects = Vec::new();
+        let mut materials = Vec::new();
+        let mut lights = Vec::new();
+        for node in scene.nodes() {
+            visit_node(
+                gl,
+                &node,
+                Mat4::identity(),
+                &buffers,
+                &images,
+                &mut objects,
+                &mut materials,
+                &mut lights,
+            )?;
+        }
+        Ok((objects, materials, lights))
+    }
+}
+
+/// Recursively walks a glTF node and its children, accumulating the node transform hierarchy into
+/// `parent_transformation`, converting each mesh primitive into an [Object] (plus its decoded
+/// [GltfMaterial]) and each punctual light into a [GltfLight].
+fn visit_node(
+    gl: &Gl,
+    node: &gltf::Node,
+    parent_transformation: Mat4,
+    buffers: &[gltf::buffer::Data],
+    images: &[gltf::image::Data],
+    objects: &mut Vec<Object>,
+    materials: &mut Vec<Vec<GltfMaterial>>,
+    lights: &mut Vec<GltfLight>,
+) -> Result<(), GltfError> {
+    let transformation = parent_transformation * Mat4::from(node.transform().matrix());
+
+    if let Some(mesh) = node.mesh() {
+        let mut object = Object::new(mesh.name().unwrap_or("glTF mesh").to_owned());
+        let mut primitive_materials = Vec::new();
+        for primitive in mesh.primitives() {
+            let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+
+            let positions: Vec<f32> = reader
+                .read_positions()
+                .ok_or_else(|| GltfError::Parse("primitive has no POSITION attribute".to_string()))?
+                .flatten()
+                .collect();
+            let normals: Vec<f32> = reader
+                .read_normals()
+                .map(|iter| iter.flatten().collect())
+                .unwrap_or_default();
+            let uvs: Vec<f32> = reader
+                .read_tex_coords(0)
+                .map(|coords| coords.into_f32().flatten().collect())
+                .unwrap_or_default();
+            let tangents: Vec<f32> = reader
+                .read_tangents()
+                .map(|iter| iter.flatten().collect())
+                .unwrap_or_default();
+            let colors: Vec<f32> = reader
+                .read_colors(0)
+                .map(|colors| colors.into_rgba_f32().flatten().collect())
+                .unwrap_or_default();
+            // Not every primitive is indexed - a common case for simple, non-shared geometry - so a
+            // missing INDICES accessor isn't an error: synthesize the identity index buffer instead
+            // of rejecting an otherwise perfectly valid primitive.
+            let indices: Vec<u32> = match reader.read_indices() {
+                Some(indices) => indices.into_u32().collect(),
+                None => (0..(positions.len() / 3) as u32).collect(),
+            };
+
+            let geometry = if normals.is_empty() {
+                Mesh::new_with_computed_normals(gl, &indices, &positions)?
+            } else {
+                Mesh::new(gl, &indices, &positions, &normals)?
+            };
+            // POSITION/NORMAL go through the constructor above; TANGENT/TEXCOORD_0/COLOR_0 are
+            // optional per the glTF spec, so they're attached afterwards, mirroring the vertex
+            // attribute names `Particles` already uses for the same data (`tangent`, `uv_coordinates`,
+            // `color`).
+            let geometry = if !tangents.is_empty() {
+                geometry.set_tangents(&tangents)?
+            } else {
+                geometry
+            };
+            let geometry = if !uvs.is_empty() {
+                geometry.set_uvs(&uvs)?
+            } else {
+                geometry
+            };
+            let geometry = if !colors.is_empty() {
+                geometry.set_colors(&colors)?
+            } else {
+                geometry
+            };
+            object.add(geometry, MeshShader::new(gl).unwrap());
+            primitive_materials.push(gltf_material(&primitive.material(), images));
+        }
+        object.set_transformation(transformation);
+        objects.push(object);
+        materials.push(primitive_materials);
+    }
+
+    if let Some(light) = node.light() {
+        lights.push(gltf_light(&light, &transformation));
+    }
+
+    for child in node.children() {
+        visit_node(gl, &child, transformation, buffers, images, objects, materials, lights)?;
+    }
+    Ok(())
+}
+
+/// Decodes a glTF material's metallic-roughness factors and textures into a [GltfMaterial].
+fn gltf_material(material: &gltf::Material, images: &[gltf::image::Data]) -> GltfMaterial {
+    let pbr = material.pbr_metallic_roughness();
+    GltfMaterial {
+        base_color_factor: pbr.base_color_factor(),
+        metallic_factor: pbr.metallic_factor(),
+        roughness_factor: pbr.roughness_factor(),
+        base_color_texture: pbr
+            .base_color_texture()
+            .map(|info| decode_image(&info.texture(), images)),
+        metallic_roughness_texture: pbr
+            .metallic_roughness_texture()
+            .map(|info| decode_image(&info.texture(), images)),
+        normal_texture: material
+            .normal_texture()
+            .map(|info| decode_image(&info.texture(), images)),
+        emissive_texture: material
+            .emissive_texture()
+            .map(|info| decode_image(&info.texture(), images)),
+    }
+}
+
+fn decode_image(texture: &gltf::Texture, images: &[gltf::image::Data]) -> (Vec<u8>, u32, u32) {
+    let image = &images[texture.source().index()];
+    (image.pixels.clone(), image.width, image.height)
+}
+
+/// Converts a glTF punctual light into a [GltfLight], taking its position/direction from the
+/// accumulated node `transformation` rather than the node's local transform, since lights (like
+/// meshes) are parented through the node hierarchy.
+fn gltf_light(light: &gltf::khr_lights_punctual::Light, transformation: &Mat4) -> GltfLight {
+    let position = (transformation * vec4(0.0, 0.0, 0.0, 1.0)).truncate();
+    let direction = (transformation * vec4(0.0, 0.0, -1.0, 0.0)).truncate().normalize();
+    let [r, g, b] = light.color();
+    let kind = match light.kind() {
+        gltf::khr_lights_punctual::Kind::Directional => GltfLightKind::Directional,
+        gltf::khr_lights_punctual::Kind::Point => GltfLightKind::Point,
+        gltf::khr_lights_punctual::Kind::Spot {
+            inner_cone_angle,
+            outer_cone_angle,
+        } => GltfLightKind::Spot {
+            inner_cone_angle,
+            outer_cone_angle,
+        },
+    };
+    GltfLight {
+        kind,
+        position,
+        direction,
+        color: vec3(r, g, b),
+        intensity: light.intensity(),
+    }
+}
+
+///
+/// An error produced while loading a glTF asset: the document itself, or one of the buffers/images
+/// it references, could not be parsed.
+///
+#[derive(Debug)]
+pub enum GltfError {
+    /// The glTF/GLB document or one of its referenced buffers/images could not be parsed.
+    Parse(String),
+}
+
+impl std::fmt::Display for GltfError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GltfError::Parse(message) => write!(f, "failed to parse glTF asset: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for GltfError {}
+
+impl From<Error> for GltfError {
+    fn from(error: Error) -> Self {
+        GltfError::Parse(error.to_string())
+    }
+}
@@ -2,6 +2,7 @@
 pub mod objects;
 pub mod geometries;
 pub mod effects;
+pub mod frame;
 pub mod light;
 pub mod renderer;
 pub mod camerahandler;
@@ -0,0 +1,136 @@
+use crate::core::*;
+use crate::light::shadow::*;
+use crate::renderer::*;
+
+///
+/// A light which shines in the given direction, for example as the light from the sun.
+/// Can cast shadows by giving it a [ShadowSettings] with [DirectionalLight::set_shadow].
+///
+pub struct DirectionalLight {
+    context: Context,
+    /// The intensity of the light. This allows for higher intensities than 1 which is necessary if you want to simulate really bright lights.
+    pub intensity: f32,
+    /// The base color of the light.
+    pub color: Color,
+    direction: Vec3,
+    shadow_settings: Option<ShadowSettings>,
+    shadow_map: Option<ShadowMap>,
+    shadow_matrix: Mat4,
+}
+
+impl DirectionalLight {
+    /// Creates a new directional light that shines in the given direction.
+    pub fn new(context: &Context, intensity: f32, color: Color, direction: &Vec3) -> Self {
+        Self {
+            context: context.clone(),
+            intensity,
+            color,
+            direction: direction.normalize(),
+            shadow_settings: None,
+            shadow_map: None,
+            shadow_matrix: Mat4::identity(),
+        }
+    }
+
+    /// The direction the light shines in.
+    pub fn direction(&self) -> Vec3 {
+        self.direction
+    }
+
+    /// Sets the direction the light shines in.
+    pub fn set_direction(&mut self, direction: &Vec3) {
+        self.direction = direction.normalize();
+    }
+
+    ///
+    /// Enables shadows cast by this light using the given [ShadowSettings]. Call
+    /// [DirectionalLight::generate_shadow_map] afterwards (and whenever the shadow casters move)
+    /// to (re)render the depth map the settings describe.
+    ///
+    pub fn set_shadow(&mut self, settings: ShadowSettings) {
+        self.shadow_map = Some(ShadowMap::new_2d(&self.context, &settings));
+        self.shadow_settings = Some(settings);
+    }
+
+    /// Disables shadows cast by this light.
+    pub fn clear_shadow(&mut self) {
+        self.shadow_settings = None;
+        self.shadow_map = None;
+    }
+
+    ///
+    /// Renders the depth of the given geometries, as seen from this light, into the shadow map,
+    /// using an orthographic frustum that tightly bounds `target +- frustum_size`. Must be called
+    /// at least once before the light can cast shadows, and again whenever the geometries move.
+    ///
+    pub fn generate_shadow_map(
+        &mut self,
+        target: Vec3,
+        frustum_size: f32,
+        geometries: &[&dyn Geometry],
+    ) {
+        let up = compute_up_direction(self.direction);
+        let view = Mat4::look_at_rh(
+            point3(
+                target.x - self.direction.x * frustum_size,
+                target.y - self.direction.y * frustum_size,
+                target.z - self.direction.z * frustum_size,
+            ),
+            point3(target.x, target.y, target.z),
+            up,
+        );
+        let projection = ortho(
+            -frustum_size,
+            frustum_size,
+            -frustum_size,
+            frustum_size,
+            0.0,
+            2.0 * frustum_size,
+        );
+        self.shadow_matrix = shadow_matrix(projection, view);
+        if let Some(ShadowMap::Texture2D(shadow_map)) = &self.shadow_map {
+            render_depth_pass(&self.context, shadow_map, projection * view, geometries);
+        }
+    }
+
+    pub(crate) fn shadow_uniforms(&self) -> (Mat4, Option<&ShadowSettings>, Option<&ShadowMap>) {
+        (self.shadow_matrix, self.shadow_settings.as_ref(), self.shadow_map.as_ref())
+    }
+}
+
+impl Light for DirectionalLight {
+    fn shader_source(&self, i: u32) -> String {
+        let shadow = shadow_shader_source(i, self.shadow_settings.as_ref());
+        format!(
+            "
+                uniform vec3 color{};
+                uniform vec3 direction{};
+                {}
+                vec3 calculate_lighting{}(vec3 surface_color, vec3 position, vec3 normal, vec3 view_direction, float metallic, float roughness, float occlusion)
+                {{
+                    float shadow = {};
+                    return calculate_light(color{}, -direction{}, surface_color, view_direction, normal, metallic, roughness) * shadow;
+                }}
+            ",
+            i, i, shadow, i,
+            if self.shadow_settings.is_some() { format!("shadow_factor{}(position)", i) } else { "1.0".to_string() },
+            i, i
+        )
+    }
+
+    fn use_uniforms(&self, program: &Program, i: u32) {
+        program.use_uniform(&format!("color{}", i), &(self.color.to_vec3() * self.intensity));
+        program.use_uniform(&format!("direction{}", i), &self.direction);
+        if let (matrix, Some(settings), Some(shadow_map)) = self.shadow_uniforms() {
+            use_shadow_uniforms(program, i, matrix, settings, shadow_map);
+        }
+    }
+}
+
+fn compute_up_direction(direction: Vec3) -> Vec3 {
+    if vec3(1.0, 0.0, 0.0).dot(direction).abs() > 0.999 {
+        (vec3(0.0, 1.0, 0.0).cross(direction)).normalize()
+    } else {
+        (vec3(1.0, 0.0, 0.0).cross(direction)).normalize()
+    }
+}
@@ -0,0 +1,50 @@
+//!
+//! Light types that can be used together with the default or a custom material to shade an
+//! object, see [crate::renderer::Light] for the interface expected by a [crate::renderer::Material].
+//! [DirectionalLight], [SpotLight] and [PointLight] can each optionally cast shadows - see their
+//! `set_shadow` method and [shadow::ShadowSettings].
+//!
+
+mod directional;
+pub use directional::*;
+
+mod spot;
+pub use spot::*;
+
+mod point;
+pub use point::*;
+
+pub mod shadow;
+pub use shadow::{ShadowFilter, ShadowSettings};
+
+use crate::core::*;
+
+///
+/// Represents how much a light source contributes to the scene, used to physically attenuate point
+/// and spot lights over distance: `1 / (constant + linear * distance + quadratic * distance^2)`.
+///
+#[derive(Clone, Copy, Debug)]
+pub struct Attenuation {
+    /// The constant term.
+    pub constant: f32,
+    /// The linear term.
+    pub linear: f32,
+    /// The quadratic term.
+    pub quadratic: f32,
+}
+
+impl Attenuation {
+    pub(crate) fn to_vec3(self) -> Vec3 {
+        vec3(self.constant, self.linear, self.quadratic)
+    }
+}
+
+impl Default for Attenuation {
+    fn default() -> Self {
+        Self {
+            constant: 1.0,
+            linear: 0.0,
+            quadratic: 0.0,
+        }
+    }
+}
@@ -0,0 +1,198 @@
+use crate::core::*;
+use crate::light::shadow::*;
+use crate::renderer::*;
+
+///
+/// A light which shines from the given position in all directions. Can cast shadows by giving it a
+/// [ShadowSettings] with [PointLight::set_shadow] - unlike directional and spot lights, a point
+/// light renders its shadow map into a cube depth texture, one render pass per face, since
+/// occlusion must be tested in every direction away from the light.
+///
+pub struct PointLight {
+    context: Context,
+    /// The intensity of the light.
+    pub intensity: f32,
+    /// The base color of the light.
+    pub color: Color,
+    /// How the intensity of the light fades with distance, see [Attenuation].
+    pub attenuation: Attenuation,
+    position: Vec3,
+    shadow_settings: Option<ShadowSettings>,
+    shadow_map: Option<ShadowMap>,
+    shadow_z_near: f32,
+    shadow_z_far: f32,
+}
+
+/// The view direction and up vector for each of the six faces of a cube shadow map, in the
+/// canonical +X, -X, +Y, -Y, +Z, -Z order.
+const CUBE_FACE_DIRECTIONS: [(Vec3, Vec3); 6] = [
+    (vec3(1.0, 0.0, 0.0), vec3(0.0, -1.0, 0.0)),
+    (vec3(-1.0, 0.0, 0.0), vec3(0.0, -1.0, 0.0)),
+    (vec3(0.0, 1.0, 0.0), vec3(0.0, 0.0, 1.0)),
+    (vec3(0.0, -1.0, 0.0), vec3(0.0, 0.0, -1.0)),
+    (vec3(0.0, 0.0, 1.0), vec3(0.0, -1.0, 0.0)),
+    (vec3(0.0, 0.0, -1.0), vec3(0.0, -1.0, 0.0)),
+];
+
+impl PointLight {
+    /// Creates a new point light shining from `position` in all directions.
+    pub fn new(
+        context: &Context,
+        intensity: f32,
+        color: Color,
+        position: &Vec3,
+        attenuation: Attenuation,
+    ) -> Self {
+        Self {
+            context: context.clone(),
+            intensity,
+            color,
+            attenuation,
+            position: *position,
+            shadow_settings: None,
+            shadow_map: None,
+            shadow_z_near: 0.1,
+            shadow_z_far: 1.0,
+        }
+    }
+
+    /// The position of the light source in world coordinates.
+    pub fn position(&self) -> Vec3 {
+        self.position
+    }
+
+    /// Sets the position of the light source.
+    pub fn set_position(&mut self, position: &Vec3) {
+        self.position = *position;
+    }
+
+    /// Enables shadows cast by this light using the given [ShadowSettings].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `settings.filter` is [ShadowFilter::Pcss] - the PCSS blocker search needs a raw
+    /// (non-comparison) depth read to estimate occluder distance, and unlike [ShadowMap::Texture2D]
+    /// there is no raw-sampled counterpart bound alongside a cube shadow map to search against. Use
+    /// [ShadowFilter::Pcf] or [ShadowFilter::Hardware2x2] with a point light instead.
+    pub fn set_shadow(&mut self, settings: ShadowSettings) {
+        assert!(
+            !matches!(settings.filter, ShadowFilter::Pcss { .. }),
+            "PointLight does not support ShadowFilter::Pcss - its cube shadow map has no raw depth \
+             texture for the PCSS blocker search to sample, unlike a 2D shadow map. Use \
+             ShadowFilter::Pcf or ShadowFilter::Hardware2x2 instead."
+        );
+        self.shadow_map = Some(ShadowMap::new_cube(&self.context, &settings));
+        self.shadow_settings = Some(settings);
+    }
+
+    /// Disables shadows cast by this light.
+    pub fn clear_shadow(&mut self) {
+        self.shadow_settings = None;
+        self.shadow_map = None;
+    }
+
+    ///
+    /// Renders the depth of the given geometries into each of the six faces of the cube shadow
+    /// map, using a 90 degree field-of-view perspective projection aimed down each cube axis.
+    ///
+    pub fn generate_shadow_map(&mut self, z_near: f32, z_far: f32, geometries: &[&dyn Geometry]) {
+        self.shadow_z_near = z_near;
+        self.shadow_z_far = z_far;
+        let Some(ShadowMap::TextureCube(shadow_map)) = &self.shadow_map else {
+            return;
+        };
+        let projection = perspective(Rad(std::f32::consts::FRAC_PI_2), 1.0, z_near, z_far);
+        for (face, (direction, up)) in CUBE_FACE_DIRECTIONS.iter().enumerate() {
+            let view = Mat4::look_at_rh(
+                point3(self.position.x, self.position.y, self.position.z),
+                point3(
+                    self.position.x + direction.x,
+                    self.position.y + direction.y,
+                    self.position.z + direction.z,
+                ),
+                *up,
+            );
+            shadow_map
+                .write_face(face, Some(1.0), || {
+                    for geometry in geometries
+                        .iter()
+                        .filter(|g| g.aabb().intersects_frustum(&(projection * view)))
+                    {
+                        geometry.render_depth(projection * view);
+                    }
+                    Ok(())
+                })
+                .unwrap();
+        }
+    }
+}
+
+impl Light for PointLight {
+    fn shader_source(&self, i: u32) -> String {
+        let has_shadow = self.shadow_settings.is_some();
+        // Mirrors the `filter_define` built in `shadow_shader_source`, but assembled here since
+        // `PointLight` emits its own shader source directly rather than going through it - see
+        // `shadow_helpers_source`.
+        let filter_define = self.shadow_settings.as_ref().map_or(String::new(), |settings| {
+            match &settings.filter {
+                ShadowFilter::None => "#define SHADOW_FILTER_NONE".to_string(),
+                ShadowFilter::Hardware2x2 => "#define SHADOW_FILTER_HARDWARE_2X2".to_string(),
+                ShadowFilter::Pcf { samples } => format!("#define SHADOW_FILTER_PCF {}", samples),
+                ShadowFilter::Pcss { .. } => unreachable!("rejected by PointLight::set_shadow"),
+            }
+        });
+        format!(
+            "
+                uniform vec3 color{};
+                uniform vec3 position{};
+                uniform vec3 attenuation{};
+                {}
+                vec3 calculate_lighting{}(vec3 surface_color, vec3 position, vec3 normal, vec3 view_direction, float metallic, float roughness, float occlusion)
+                {{
+                    vec3 light_direction = normalize(position{} - position);
+                    float dist = length(position{} - position);
+                    float att = 1.0 / (attenuation{}.x + attenuation{}.y * dist + attenuation{}.z * dist * dist);
+                    float shadow = {};
+                    return calculate_light(color{}, light_direction, surface_color, view_direction, normal, metallic, roughness) * att * shadow;
+                }}
+            ",
+            i, i, i,
+            if has_shadow {
+                format!(
+                    "
+                     {}
+                     #ifndef SHADOW_HELPERS_INCLUDED
+                     #define SHADOW_HELPERS_INCLUDED
+                     {}
+                     #endif
+                     uniform samplerCubeShadow shadowMap{}; uniform float shadowZNear{}; uniform float shadowZFar{}; uniform float depthBias{}; uniform float slopeScaledBias{};
+                     float shadow_factor{}(vec3 worldPosition) {{ return sample_cube_shadow_map(shadowMap{}, position{}, shadowZNear{}, shadowZFar{}, worldPosition, depthBias{}, slopeScaledBias{}); }}",
+                    // `filter_define` must precede `SHADOW_HELPERS` for the same reason as in
+                    // `shadow_shader_source`: the `#if defined(SHADOW_FILTER_*)` chain inside it is
+                    // evaluated top-to-bottom and needs the macro already defined.
+                    filter_define, shadow_helpers_source(), i, i, i, i, i, i, i, i, i, i, i, i
+                )
+            } else {
+                String::new()
+            },
+            i, i, i, i, i, i,
+            if has_shadow { format!("shadow_factor{}(position)", i) } else { "1.0".to_string() },
+            i
+        )
+    }
+
+    fn use_uniforms(&self, program: &Program, i: u32) {
+        program.use_uniform(&format!("color{}", i), &(self.color.to_vec3() * self.intensity));
+        program.use_uniform(&format!("position{}", i), &self.position);
+        program.use_uniform(&format!("attenuation{}", i), &self.attenuation.to_vec3());
+        if let Some(settings) = &self.shadow_settings {
+            if let Some(ShadowMap::TextureCube(texture)) = &self.shadow_map {
+                program.use_uniform(&format!("shadowZNear{}", i), &self.shadow_z_near);
+                program.use_uniform(&format!("shadowZFar{}", i), &self.shadow_z_far);
+                program.use_uniform(&format!("depthBias{}", i), &settings.depth_bias);
+                program.use_uniform(&format!("slopeScaledBias{}", i), &settings.slope_scaled_bias);
+                program.use_depth_texture_cube(&format!("shadowMap{}", i), texture);
+            }
+        }
+    }
+}
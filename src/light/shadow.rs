@@ -0,0 +1,221 @@
+use crate::core::*;
+
+///
+/// Controls how a shadow map is filtered when sampled during shading, trading sharpness for
+/// softness and cost. Attached to a light via its `set_shadow` method - see [crate::light::DirectionalLight],
+/// [crate::light::SpotLight] and [crate::light::PointLight].
+///
+#[derive(Clone, Debug)]
+pub struct ShadowSettings {
+    /// The resolution of the depth texture(s) the light renders its shadow map into.
+    pub texture_size: u32,
+    /// A constant offset subtracted from the fragment's depth before it is compared against the
+    /// shadow map, used to push the compared surface slightly towards the light to avoid self-shadowing (shadow acne).
+    pub depth_bias: f32,
+    /// An additional bias term scaled by the slope of the surface relative to the light, since a
+    /// constant bias alone is insufficient for grazing angles.
+    pub slope_scaled_bias: f32,
+    /// The filtering mode applied when comparing fragment depth against the shadow map.
+    pub filter: ShadowFilter,
+}
+
+impl Default for ShadowSettings {
+    fn default() -> Self {
+        Self {
+            texture_size: 1024,
+            depth_bias: 0.005,
+            slope_scaled_bias: 0.01,
+            filter: ShadowFilter::Pcf { samples: 16 },
+        }
+    }
+}
+
+///
+/// The filtering mode used when sampling a shadow map, selectable per light through [ShadowSettings::filter].
+///
+#[derive(Clone, Debug)]
+pub enum ShadowFilter {
+    /// A single hardware depth comparison - cheapest, but produces hard-edged (aliased) shadows.
+    None,
+    /// A single comparison through a bilinear comparison sampler, softening the shadow edge over
+    /// roughly one texel without any additional texture fetches.
+    Hardware2x2,
+    /// Averages `samples` comparisons taken over a kernel around the shadow map texel, jittered on
+    /// a precomputed Poisson disc to hide the banding a regular grid of samples would produce.
+    Pcf {
+        /// The number of comparison samples taken per shaded fragment.
+        samples: u32,
+    },
+    /// Percentage-closer soft shadows: first averages the depths of occluders found within
+    /// `blocker_search_samples` taps of a search radius to estimate a blocker distance, derives a
+    /// penumbra width `w = (d_receiver - d_blocker) / d_blocker * light_size`, then runs PCF with a
+    /// kernel scaled by `w` - so contact shadows stay sharp while distant shadows soften.
+    Pcss {
+        /// The number of taps used to search for occluders and estimate the blocker distance.
+        blocker_search_samples: u32,
+        /// The apparent size of the light, in world units, used to convert blocker distance into
+        /// penumbra width.
+        penumbra_scale: f32,
+    },
+}
+
+///
+/// A depth-only render target a light renders scene depth into from its own point of view, and
+/// that the shading pass later samples to test fragment occlusion. Directional and spot lights use
+/// a single 2D depth texture; point lights use a cube depth texture so every direction away from
+/// the light is covered.
+///
+pub enum ShadowMap {
+    /// Used by [crate::light::DirectionalLight] and [crate::light::SpotLight].
+    Texture2D(DepthTargetTexture2D),
+    /// Used by [crate::light::PointLight] - one render pass per cube face.
+    TextureCube(DepthTargetTextureCubeMap),
+}
+
+impl ShadowMap {
+    pub(crate) fn new_2d(context: &Context, settings: &ShadowSettings) -> Self {
+        ShadowMap::Texture2D(DepthTargetTexture2D::new(
+            context,
+            settings.texture_size,
+            settings.texture_size,
+            Wrapping::ClampToEdge,
+            Wrapping::ClampToEdge,
+            DepthFormat::Depth32F,
+        ))
+    }
+
+    pub(crate) fn new_cube(context: &Context, settings: &ShadowSettings) -> Self {
+        ShadowMap::TextureCube(DepthTargetTextureCubeMap::new(
+            context,
+            settings.texture_size,
+            settings.texture_size,
+            Wrapping::ClampToEdge,
+            Wrapping::ClampToEdge,
+            DepthFormat::Depth32F,
+        ))
+    }
+}
+
+/// Bias matrix mapping clip space `[-1, 1]` into texture space `[0, 1]`, folded into the
+/// light's view-projection so the fragment shader can sample the shadow map directly with the
+/// transformed position.
+const BIAS_MATRIX: Mat4 = Mat4::new(
+    0.5, 0.0, 0.0, 0.0, 0.0, 0.5, 0.0, 0.0, 0.0, 0.0, 0.5, 0.0, 0.5, 0.5, 0.5, 1.0,
+);
+
+/// Combines a light's projection and view matrices with the [BIAS_MATRIX] into the matrix used to
+/// transform a world space position into shadow map texture space.
+pub(crate) fn shadow_matrix(projection: Mat4, view: Mat4) -> Mat4 {
+    BIAS_MATRIX * projection * view
+}
+
+/// The raw GLSL of `sample_shadow_map`/`sample_cube_shadow_map`, pulled in once (behind an include
+/// guard, since several shadowed lights in the same draw call each call [shadow_shader_source])
+/// rather than left for every light site to `include_str!` separately and risk duplicate
+/// definitions or, worse, never including it at all.
+const SHADOW_HELPERS: &str = include_str!("shaders/shadow.frag");
+
+/// The raw GLSL of [SHADOW_HELPERS], for light types like [crate::light::PointLight] that assemble
+/// their own shader source directly instead of going through [shadow_shader_source].
+pub(crate) fn shadow_helpers_source() -> &'static str {
+    SHADOW_HELPERS
+}
+
+///
+/// Generates the GLSL declarations and helper function needed to sample light `i`'s 2D shadow map
+/// according to its [ShadowSettings::filter], or an empty string if the light casts no shadow.
+/// Shared by directional and spot lights; [crate::light::PointLight] assembles its own cube map
+/// equivalent instead (see [shadow_helpers_source]).
+///
+pub(crate) fn shadow_shader_source(i: u32, settings: Option<&ShadowSettings>) -> String {
+    let Some(settings) = settings else {
+        return String::new();
+    };
+    // Every branch is a value-carrying `#define` (not a bare flag) since `shadow.frag` uses
+    // `SHADOW_FILTER_PCF`/`SHADOW_FILTER_PCSS` directly as integer sample counts.
+    let filter_define = match &settings.filter {
+        ShadowFilter::None => "#define SHADOW_FILTER_NONE".to_string(),
+        ShadowFilter::Hardware2x2 => "#define SHADOW_FILTER_HARDWARE_2X2".to_string(),
+        ShadowFilter::Pcf { samples } => format!("#define SHADOW_FILTER_PCF {}", samples),
+        ShadowFilter::Pcss {
+            blocker_search_samples,
+            ..
+        } => format!("#define SHADOW_FILTER_PCSS {}", blocker_search_samples),
+    };
+    format!(
+        "
+            {}
+            #ifndef SHADOW_HELPERS_INCLUDED
+            #define SHADOW_HELPERS_INCLUDED
+            {}
+            #endif
+            uniform mat4 shadowMatrix{};
+            uniform sampler2DShadow shadowMap{};
+            uniform sampler2D shadowMapRaw{};
+            uniform float depthBias{};
+            uniform float slopeScaledBias{};
+            uniform float penumbraScale{};
+            float shadow_factor{}(vec3 position)
+            {{
+                return sample_shadow_map(shadowMap{}, shadowMapRaw{}, shadowMatrix{}, position, depthBias{}, slopeScaledBias{}, penumbraScale{});
+            }}
+        ",
+        // `filter_define` must precede `SHADOW_HELPERS` - GLSL preprocessing is single-pass
+        // top-to-bottom, and `SHADOW_HELPERS`'s `#if defined(SHADOW_FILTER_*)` chain needs the
+        // macro to already be defined by the time it's evaluated.
+        filter_define, SHADOW_HELPERS, i, i, i, i, i, i, i, i, i, i, i, i, i
+    )
+}
+
+/// Uploads the depth-bias, slope-scaled-bias, penumbra-scale and shadow-matrix uniforms common to
+/// every shadowed light type, along with the shadow map texture itself.
+pub(crate) fn use_shadow_uniforms(
+    program: &Program,
+    i: u32,
+    shadow_matrix: Mat4,
+    settings: &ShadowSettings,
+    shadow_map: &ShadowMap,
+) {
+    program.use_uniform(&format!("shadowMatrix{}", i), &shadow_matrix);
+    program.use_uniform(&format!("depthBias{}", i), &settings.depth_bias);
+    program.use_uniform(&format!("slopeScaledBias{}", i), &settings.slope_scaled_bias);
+    let penumbra_scale = match &settings.filter {
+        ShadowFilter::Pcss { penumbra_scale, .. } => *penumbra_scale,
+        _ => 0.0,
+    };
+    program.use_uniform(&format!("penumbraScale{}", i), &penumbra_scale);
+    match shadow_map {
+        ShadowMap::Texture2D(texture) => {
+            program.use_depth_texture(&format!("shadowMap{}", i), texture);
+            // The PCSS blocker search can't use `shadowMap{i}` for this - it's bound through a
+            // comparison sampler, so reading it returns a pass/fail result (with `ref = 1.0`) and
+            // never the occluder's actual depth. Bind the same depth texture a second time through
+            // a plain (non-comparison) sampler so the blocker search can read real depth values.
+            program.use_depth_texture_as_color(&format!("shadowMapRaw{}", i), texture);
+        }
+        ShadowMap::TextureCube(texture) => program.use_depth_texture_cube(&format!("shadowMap{}", i), texture),
+    }
+}
+
+///
+/// Renders the depth of `geometries` as seen from `view_projection` into `shadow_map`, clearing it
+/// first. Shared by all three light types so the occluder pass itself only needs to be written once.
+///
+pub(crate) fn render_depth_pass(
+    context: &Context,
+    shadow_map: &DepthTargetTexture2D,
+    view_projection: Mat4,
+    geometries: &[&dyn Geometry],
+) {
+    shadow_map
+        .write(Some(1.0), || {
+            for geometry in geometries
+                .iter()
+                .filter(|g| g.aabb().intersects_frustum(&view_projection))
+            {
+                geometry.render_depth(view_projection);
+            }
+            Ok(())
+        })
+        .unwrap();
+}
@@ -0,0 +1,150 @@
+use crate::core::*;
+use crate::light::shadow::*;
+use crate::renderer::*;
+
+///
+/// A light which shines from the given position and in the given direction, restricted to a cone
+/// described by `cutoff` (the half-angle, in radians, of the cone). Can cast shadows by giving it a
+/// [ShadowSettings] with [SpotLight::set_shadow].
+///
+pub struct SpotLight {
+    context: Context,
+    /// The intensity of the light.
+    pub intensity: f32,
+    /// The base color of the light.
+    pub color: Color,
+    /// How the intensity of the light fades with distance, see [Attenuation].
+    pub attenuation: Attenuation,
+    position: Vec3,
+    direction: Vec3,
+    cutoff: Radians,
+    shadow_settings: Option<ShadowSettings>,
+    shadow_map: Option<ShadowMap>,
+    shadow_matrix: Mat4,
+}
+
+impl SpotLight {
+    /// Creates a new spot light shining from `position` in `direction`, restricted to a cone of
+    /// half-angle `cutoff`.
+    pub fn new(
+        context: &Context,
+        intensity: f32,
+        color: Color,
+        position: &Vec3,
+        direction: &Vec3,
+        cutoff: Radians,
+        attenuation: Attenuation,
+    ) -> Self {
+        Self {
+            context: context.clone(),
+            intensity,
+            color,
+            attenuation,
+            position: *position,
+            direction: direction.normalize(),
+            cutoff,
+            shadow_settings: None,
+            shadow_map: None,
+            shadow_matrix: Mat4::identity(),
+        }
+    }
+
+    /// The position of the light source in world coordinates.
+    pub fn position(&self) -> Vec3 {
+        self.position
+    }
+
+    /// Sets the position of the light source.
+    pub fn set_position(&mut self, position: &Vec3) {
+        self.position = *position;
+    }
+
+    /// The direction the light shines in.
+    pub fn direction(&self) -> Vec3 {
+        self.direction
+    }
+
+    /// Sets the direction the light shines in.
+    pub fn set_direction(&mut self, direction: &Vec3) {
+        self.direction = direction.normalize();
+    }
+
+    /// Enables shadows cast by this light using the given [ShadowSettings].
+    pub fn set_shadow(&mut self, settings: ShadowSettings) {
+        self.shadow_map = Some(ShadowMap::new_2d(&self.context, &settings));
+        self.shadow_settings = Some(settings);
+    }
+
+    /// Disables shadows cast by this light.
+    pub fn clear_shadow(&mut self) {
+        self.shadow_settings = None;
+        self.shadow_map = None;
+    }
+
+    ///
+    /// Renders the depth of the given geometries, as seen from this light's own perspective
+    /// projection (field of view `2 * cutoff`), into the shadow map.
+    ///
+    pub fn generate_shadow_map(&mut self, z_near: f32, z_far: f32, geometries: &[&dyn Geometry]) {
+        let up = if self.direction.dot(vec3(0.0, 1.0, 0.0)).abs() > 0.999 {
+            vec3(0.0, 0.0, 1.0)
+        } else {
+            vec3(0.0, 1.0, 0.0)
+        };
+        let view = Mat4::look_at_rh(
+            point3(self.position.x, self.position.y, self.position.z),
+            point3(
+                self.position.x + self.direction.x,
+                self.position.y + self.direction.y,
+                self.position.z + self.direction.z,
+            ),
+            up,
+        );
+        let projection = perspective(Rad(2.0 * self.cutoff.0), 1.0, z_near, z_far);
+        self.shadow_matrix = shadow_matrix(projection, view);
+        if let Some(ShadowMap::Texture2D(shadow_map)) = &self.shadow_map {
+            render_depth_pass(&self.context, shadow_map, projection * view, geometries);
+        }
+    }
+}
+
+impl Light for SpotLight {
+    fn shader_source(&self, i: u32) -> String {
+        let shadow = shadow_shader_source(i, self.shadow_settings.as_ref());
+        format!(
+            "
+                uniform vec3 color{};
+                uniform vec3 position{};
+                uniform vec3 direction{};
+                uniform float cutoff{};
+                uniform vec3 attenuation{};
+                {}
+                vec3 calculate_lighting{}(vec3 surface_color, vec3 position, vec3 normal, vec3 view_direction, float metallic, float roughness, float occlusion)
+                {{
+                    vec3 light_direction = normalize(position{} - position);
+                    float spot = smoothstep(cos(cutoff{}), cos(cutoff{} * 0.9), dot(-light_direction, normalize(direction{})));
+                    float shadow = {};
+                    float dist = length(position{} - position);
+                    float att = 1.0 / (attenuation{}.x + attenuation{}.y * dist + attenuation{}.z * dist * dist);
+                    return calculate_light(color{}, light_direction, surface_color, view_direction, normal, metallic, roughness) * spot * att * shadow;
+                }}
+            ",
+            i, i, i, i, i, shadow, i, i, i, i, i,
+            if self.shadow_settings.is_some() { format!("shadow_factor{}(position)", i) } else { "1.0".to_string() },
+            i, i, i, i, i
+        )
+    }
+
+    fn use_uniforms(&self, program: &Program, i: u32) {
+        program.use_uniform(&format!("color{}", i), &(self.color.to_vec3() * self.intensity));
+        program.use_uniform(&format!("position{}", i), &self.position);
+        program.use_uniform(&format!("direction{}", i), &self.direction);
+        program.use_uniform(&format!("cutoff{}", i), &self.cutoff.0);
+        program.use_uniform(&format!("attenuation{}", i), &self.attenuation.to_vec3());
+        if let Some(settings) = &self.shadow_settings {
+            if let Some(shadow_map) = &self.shadow_map {
+                use_shadow_uniforms(program, i, self.shadow_matrix, settings, shadow_map);
+            }
+        }
+    }
+}
@@ -0,0 +1,156 @@
+use crate::core::*;
+
+///
+/// Opt-in culling for instanced geometry such as [crate::renderer::geometry::Particles]. Given a
+/// per-instance bounding sphere, each instance is tested against the camera frustum planes and,
+/// for [Culling::Occlusion], against a hierarchical-Z depth pyramid, and only the surviving
+/// instances are drawn. The per-instance tests run on the CPU (against the pyramid levels read
+/// back in [HiZPyramid::build]); the survivors' attributes are gathered into compacted instance
+/// buffers and drawn with a reduced instance count. No GPU culling pass or indirect draw is used.
+///
+#[derive(Clone, Debug, Default)]
+pub enum Culling {
+    /// No culling: every instance is drawn, as before.
+    #[default]
+    None,
+    /// Reject instances whose bounding sphere lies fully outside the camera frustum.
+    Frustum,
+    /// Frustum culling, followed by a hierarchical-Z occlusion test against `pyramid`.
+    Occlusion {
+        /// The depth pyramid to test instances against, built with [HiZPyramid::build] from a
+        /// depth prepass of the same frame.
+        pyramid: HiZPyramid,
+    },
+}
+
+///
+/// A hierarchical-Z depth pyramid: a depth prepass downsampled into successive mip levels, each
+/// texel holding the *farthest* (max) depth of the corresponding 2x2 block in the level below. An
+/// instance's bounding sphere is projected to screen space, the mip whose texel footprint covers
+/// the projected extent is selected, and the instance is rejected if its nearest depth is farther
+/// than the stored max depth at that location - i.e. something opaque already fully covers it.
+///
+pub struct HiZPyramid {
+    levels: Vec<Texture2D>,
+    /// The full contents of every level in `levels`, read back once here at build time rather than
+    /// one texel at a time per tested instance in [HiZPyramid::sample_depth] - a synchronous
+    /// GPU->CPU round trip per instance, per frame, was dwarfing the cost of simply drawing every
+    /// instance outright.
+    cpu_levels: Vec<(u32, u32, Vec<f32>)>,
+}
+
+impl HiZPyramid {
+    ///
+    /// Builds a Hi-Z pyramid from `depth_prepass`: level 0 is a direct copy, and each subsequent
+    /// level downsamples the previous one by taking the max depth of each 2x2 texel block, until a
+    /// 1x1 level is reached. Every level is read back into CPU memory once here - a handful of bulk
+    /// reads per frame, not one per tested instance.
+    ///
+    pub fn build(context: &Context, depth_prepass: &DepthTargetTexture2D) -> Self {
+        let mut levels = Vec::new();
+        let mut width = depth_prepass.width();
+        let mut height = depth_prepass.height();
+        let mut previous = Texture2D::copy_from_depth(context, depth_prepass);
+        levels.push(previous.clone());
+        while width > 1 || height > 1 {
+            width = (width / 2).max(1);
+            height = (height / 2).max(1);
+            let level = Texture2D::new_empty(context, width, height, Format::R32F);
+            downsample_max(context, &previous, &level);
+            levels.push(level.clone());
+            previous = level;
+        }
+        let cpu_levels = levels
+            .iter()
+            .map(|level| (level.width(), level.height(), level.read()))
+            .collect();
+        Self { levels, cpu_levels }
+    }
+
+    /// The mip level whose texel footprint most tightly covers a projected screen-space sphere of
+    /// radius `pixel_radius` pixels (i.e. a texel at least `2 * pixel_radius` pixels wide) - larger
+    /// footprints select coarser (smaller) mips, same as standard texture-space mip selection.
+    pub fn level_for_pixel_radius(&self, pixel_radius: f32) -> usize {
+        let level = (2.0 * pixel_radius).max(1.0).log2().ceil().max(0.0) as usize;
+        level.min(self.levels.len() - 1)
+    }
+
+    pub(crate) fn texture(&self, level: usize) -> &Texture2D {
+        &self.levels[level]
+    }
+
+    /// Looks up the stored max depth at `ndc_xy` (normalized device coordinates, each in `[-1, 1]`)
+    /// from the given mip level's CPU-side copy, taken once in [HiZPyramid::build]. Used by the
+    /// CPU-driven occlusion test in [crate::renderer::geometry::Particles]; a fully GPU-resident
+    /// culling pass would instead sample this texture directly in a compute/vertex stage.
+    pub fn sample_depth(&self, level: usize, ndc_xy: (f32, f32)) -> f32 {
+        let (width, height, pixels) = &self.cpu_levels[level.min(self.cpu_levels.len() - 1)];
+        let uv = ((ndc_xy.0 * 0.5 + 0.5), (ndc_xy.1 * 0.5 + 0.5));
+        let x = ((uv.0.clamp(0.0, 1.0)) * (*width - 1) as f32).round() as u32;
+        let y = ((uv.1.clamp(0.0, 1.0)) * (*height - 1) as f32).round() as u32;
+        pixels[(y * width + x) as usize]
+    }
+}
+
+/// Renders `destination` by sampling the 2x2 texel block of `source` beneath each output texel and
+/// keeping the max (farthest) depth - one step of the Hi-Z mip chain.
+fn downsample_max(context: &Context, source: &Texture2D, destination: &Texture2D) {
+    destination
+        .write(|| {
+            context
+                .program(DOWNSAMPLE_MAX_VERT, DOWNSAMPLE_MAX_FRAG, |program| {
+                    program.use_texture("source", source);
+                    program.draw_fullscreen_triangle();
+                })
+                .unwrap();
+            Ok(())
+        })
+        .unwrap();
+}
+
+const DOWNSAMPLE_MAX_VERT: &str = include_str!("shaders/fullscreen.vert");
+const DOWNSAMPLE_MAX_FRAG: &str = include_str!("shaders/downsample_max.frag");
+
+///
+/// A sphere in world space used as the conservative bounding volume for per-instance culling -
+/// cheaper to test against a frustum and a Hi-Z pyramid than a full AABB.
+///
+#[derive(Clone, Copy, Debug)]
+pub struct BoundingSphere {
+    /// The center of the sphere, in world coordinates.
+    pub center: Vec3,
+    /// The radius of the sphere.
+    pub radius: f32,
+}
+
+impl BoundingSphere {
+    /// Tests whether this sphere lies fully outside any of the 6 frustum planes of `view_projection`.
+    pub fn outside_frustum(&self, view_projection: &Mat4) -> bool {
+        for row in 0..3 {
+            for sign in [-1.0, 1.0] {
+                let plane = frustum_plane(view_projection, row, sign);
+                // The extracted plane is not unit length; normalize so `distance` is in the same
+                // units as `radius`.
+                let normal = plane.truncate();
+                let distance = (normal.dot(self.center) + plane.w) / normal.magnitude();
+                if distance < -self.radius {
+                    return true;
+                }
+            }
+        }
+        false
+    }
+}
+
+/// Extracts one of the 6 frustum planes (in `ax + by + cz + d = 0` form, with the normal pointing
+/// inward) from a combined view-projection matrix using the standard Gribb/Hartmann method.
+fn frustum_plane(m: &Mat4, row: usize, sign: f32) -> Vec4 {
+    let last = m.row(3);
+    let selected = m.row(row);
+    vec4(
+        last.x + sign * selected.x,
+        last.y + sign * selected.y,
+        last.z + sign * selected.z,
+        last.w + sign * selected.w,
+    )
+}
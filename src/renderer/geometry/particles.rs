@@ -1,6 +1,9 @@
 use crate::core::*;
+use crate::renderer::culling::{BoundingSphere, Culling, HiZPyramid};
+use crate::renderer::shader_preprocessor::{ShaderError, ShaderPreprocessor, ShaderVariables};
 use crate::renderer::*;
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
 
 ///
 /// Used for defining the attributes for each particle in [Particles], for example its starting position and velocity.
@@ -66,10 +69,16 @@ impl ParticleData {
 ///
 /// The particles will therefore only move if the [Particles::time] variable is updated every frame.
 ///
+/// This is the default [ParticleSimulation::Analytic] mode. Call [Particles::set_simulation] with
+/// [ParticleSimulation::Simulated] instead to step particle state on the GPU each
+/// [Particles::animate] call, which supports per-particle forces, finite lifetimes and recycling.
+///
 pub struct Particles {
     context: Context,
     vertex_buffers: HashMap<String, VertexBuffer>,
     instance_buffers: HashMap<String, InstanceBuffer>,
+    simulation_buffers: Option<SimulationBuffers>,
+    simulation: ParticleSimulation,
     index_buffer: Option<ElementBuffer>,
     /// The acceleration applied to all particles defined in the world coordinate system. Default is gravity.
     pub acceleration: Vec3,
@@ -78,6 +87,158 @@ pub struct Particles {
     texture_transform: Mat3,
     /// A time variable that should be updated each frame.
     pub time: f32,
+    culling: Culling,
+    bounding_spheres: Vec<BoundingSphere>,
+    aabb: AxisAlignedBoundingBox,
+    mesh_radius: f32,
+    data: ParticleData,
+    /// Instance buffers holding the culled/compacted subset of `instance_buffers`, re-filled in
+    /// place each frame by [Particles::compact_instance_buffers] instead of being reallocated.
+    /// Behind a [RefCell] since `render_with_material` only takes `&self`.
+    compacted_buffers: RefCell<HashMap<String, InstanceBuffer>>,
+}
+
+///
+/// Determines how the positions of a set of [Particles] evolve over time.
+///
+#[derive(Clone, Debug)]
+pub enum ParticleSimulation {
+    /// The default: positions are evaluated analytically in the vertex shader from
+    /// `start_position`, `start_velocity` and [Particles::time], as described in [Particles].
+    Analytic,
+    /// Positions, velocities and ages are integrated on the GPU each [Particles::animate] call,
+    /// using the given [SimulationConfig].
+    Simulated(SimulationConfig),
+}
+
+///
+/// Configuration for [ParticleSimulation::Simulated].
+///
+#[derive(Clone, Debug)]
+pub struct SimulationConfig {
+    /// A GLSL expression of type `vec3`, evaluated in terms of the in-scope `position` and
+    /// `velocity` variables, that computes the force acting on a particle at its current state.
+    /// For example `"vec3(0.0, -9.82, 0.0)"` for uniform gravity.
+    pub force: String,
+    /// The lifetime, in seconds, a particle survives before being re-emitted from its seed
+    /// (`start_position`/`start_velocity`) values.
+    pub lifetime: f32,
+    /// A constant offset added to `start_position` whenever a particle is re-emitted. Every
+    /// re-emission of a given particle starts from the same point, `start_position +
+    /// emitter_region`; no per-emission variation is applied. Defaults to the zero vector.
+    pub emitter_region: Vec3,
+}
+
+impl Default for SimulationConfig {
+    fn default() -> Self {
+        Self {
+            force: "vec3(0.0, -9.82, 0.0)".to_string(),
+            lifetime: 5.0,
+            emitter_region: vec3(0.0, 0.0, 0.0),
+        }
+    }
+}
+
+///
+/// The ping-pong pair of instance buffers holding the simulated particle state
+/// (`sim_position`, `sim_velocity`, `sim_age`), plus the index of the buffer that currently holds
+/// the latest state.
+///
+/// Named with a `sim_` prefix rather than plain `position`/`velocity`/`age` because `position` is
+/// already bound, with a different (per-vertex, not per-instance) divisor, as the mesh's own vertex
+/// attribute in the same program - binding both under the same name to two different buffers/divisors
+/// is undefined behaviour, not just a naming clash.
+///
+struct SimulationBuffers {
+    state: [HashMap<String, InstanceBuffer>; 2],
+    read_index: usize,
+}
+
+impl SimulationBuffers {
+    const ATTRIBUTES: [&'static str; 3] = ["sim_position", "sim_velocity", "sim_age"];
+
+    /// Creates the ping-pong buffers seeded from `start_positions`/`start_velocities`, with `age`
+    /// set to `lifetime` so every particle is treated as due for (re-)emission on the very first
+    /// `step` rather than spawning at the world origin with zero velocity for one full lifetime.
+    fn new(context: &Context, start_positions: &[Vec3], start_velocities: &[Vec3], lifetime: f32) -> Self {
+        let instance_count = start_positions.len();
+        let make = || {
+            let mut buffers = HashMap::new();
+            buffers.insert(
+                "sim_position".to_string(),
+                InstanceBuffer::new_with_data(context, start_positions),
+            );
+            buffers.insert(
+                "sim_velocity".to_string(),
+                InstanceBuffer::new_with_data(context, start_velocities),
+            );
+            buffers.insert(
+                "sim_age".to_string(),
+                InstanceBuffer::new_with_data(context, &vec![lifetime; instance_count]),
+            );
+            buffers
+        };
+        Self {
+            state: [make(), make()],
+            read_index: 0,
+        }
+    }
+
+    fn read(&self) -> &HashMap<String, InstanceBuffer> {
+        &self.state[self.read_index]
+    }
+
+    fn write(&self) -> &HashMap<String, InstanceBuffer> {
+        &self.state[1 - self.read_index]
+    }
+
+    ///
+    /// Integrates `velocity += force(position, velocity)*dt; position += velocity*dt; age += dt`
+    /// for every particle via transform feedback, writing the result into the currently
+    /// inactive buffer, then swaps the read/write buffers so the next render call (and the next
+    /// `step`) sees the updated state. When a particle's age exceeds `config.lifetime` it is
+    /// instead reset from its immutable `start_position`/`start_velocity` seed.
+    ///
+    fn step(
+        &mut self,
+        context: &Context,
+        seed_buffers: &HashMap<String, InstanceBuffer>,
+        instance_count: u32,
+        dt: f32,
+        config: &SimulationConfig,
+    ) {
+        // `force` must come before `particle_simulation.vert`'s body, which calls it - GLSL
+        // requires every function to be declared above its first use, unlike Rust.
+        let vertex_shader_source = format!(
+            "vec3 force(vec3 position, vec3 velocity) {{ return {}; }}\n{}",
+            config.force,
+            include_str!("shaders/particle_simulation.vert"),
+        );
+        context
+            .program_with_transform_feedback(
+                &vertex_shader_source,
+                Self::ATTRIBUTES,
+                |program| {
+                    program.use_uniform("dt", &dt);
+                    program.use_uniform("lifetime", &config.lifetime);
+                    program.use_uniform("emitterRegion", &config.emitter_region);
+                    for attribute in Self::ATTRIBUTES {
+                        program.use_instance_attribute(attribute, self.read().get(attribute).unwrap());
+                    }
+                    program.use_instance_attribute(
+                        "start_position",
+                        seed_buffers.get("start_position").unwrap(),
+                    );
+                    program.use_instance_attribute(
+                        "start_velocity",
+                        seed_buffers.get("start_velocity").unwrap(),
+                    );
+                    program.transform_feedback_into(self.write(), instance_count);
+                },
+            )
+            .expect("Failed compiling particle simulation shader");
+        self.read_index = 1 - self.read_index;
+    }
 }
 
 impl Particles {
@@ -93,16 +254,82 @@ impl Particles {
             index_buffer: super::index_buffer_from_mesh(context, cpu_mesh),
             vertex_buffers: super::vertex_buffers_from_mesh(context, cpu_mesh),
             instance_buffers: HashMap::new(),
+            simulation_buffers: None,
+            simulation: ParticleSimulation::Analytic,
             acceleration: vec3(0.0, -9.82, 0.0),
             instance_count: 0,
             transformation: Mat4::identity(),
             texture_transform: Mat3::identity(),
             time: 0.0,
+            culling: Culling::None,
+            bounding_spheres: Vec::new(),
+            aabb: AxisAlignedBoundingBox::EMPTY,
+            mesh_radius: cpu_mesh.aabb().size().magnitude() * 0.5,
+            data: ParticleData::default(),
+            compacted_buffers: RefCell::new(HashMap::new()),
         };
         particles.update(data);
         particles
     }
 
+    ///
+    /// Sets the opt-in culling mode (tested per instance on the CPU, see [Culling]) used to avoid issuing draw work for instances that are
+    /// outside the camera frustum (and, for [Culling::Occlusion], hidden behind other geometry).
+    /// Defaults to [Culling::None], which draws every instance exactly as before.
+    ///
+    pub fn set_culling(&mut self, culling: Culling) {
+        self.culling = culling;
+    }
+
+    ///
+    /// Sets how the particle positions evolve over time. The default is [ParticleSimulation::Analytic],
+    /// which evaluates the closed-form kinematic equation described in [Particles] directly in the
+    /// vertex shader. [ParticleSimulation::Simulated] instead steps particle state (position, velocity,
+    /// age) on the GPU each [Particles::animate] call, which allows per-particle forces, finite
+    /// lifetimes and recycling.
+    ///
+    pub fn set_simulation(&mut self, simulation: ParticleSimulation) {
+        if let ParticleSimulation::Simulated(config) = &simulation {
+            let lifetime = config.lifetime;
+            self.simulation_buffers.get_or_insert_with(|| {
+                SimulationBuffers::new(
+                    &self.context,
+                    &self.data.start_positions,
+                    &self.data.start_velocities,
+                    lifetime,
+                )
+            });
+        }
+        self.simulation = simulation;
+    }
+
+    ///
+    /// Advances the simulation by `dt` seconds. Only has an effect when [ParticleSimulation::Simulated]
+    /// has been set with [Particles::set_simulation] - the analytic mode is instead advanced implicitly
+    /// by updating [Particles::time].
+    ///
+    pub fn animate(&mut self, dt: f32) {
+        let config = match &self.simulation {
+            ParticleSimulation::Analytic => return,
+            ParticleSimulation::Simulated(config) => config.clone(),
+        };
+        let buffers = self.simulation_buffers.get_or_insert_with(|| {
+            SimulationBuffers::new(
+                &self.context,
+                &self.data.start_positions,
+                &self.data.start_velocities,
+                config.lifetime,
+            )
+        });
+        buffers.step(
+            &self.context,
+            &self.instance_buffers,
+            self.instance_count,
+            dt,
+            &config,
+        );
+    }
+
     ///
     /// Returns local to world transformation applied to the particle geometry before its position is updated as described in [Particles].
     ///
@@ -138,9 +365,33 @@ impl Particles {
     pub fn update(&mut self, data: &ParticleData) {
         #[cfg(debug_assertions)]
         data.validate().expect("invalid particle data");
+        self.data = data.clone();
         self.instance_count = data.count();
         self.instance_buffers.clear();
 
+        // The ping-pong `sim_*` buffers are sized and seeded from the *previous* `data`; left
+        // alone, a particle count change here would leave them at the old length/seeds while
+        // `animate`/`render_with_material` drive the new `instance_count` against the freshly
+        // rebuilt `instance_buffers` above - a length mismatch in both transform feedback and the
+        // draw. Reseed them immediately (at the new data's size) if simulation is active, so
+        // rendering right after `update` - before the next `animate` - is already consistent;
+        // otherwise drop them so a later `set_simulation(Simulated(..))` recreates them fresh
+        // instead of reusing stale buffers sized for data that no longer exists.
+        match &self.simulation {
+            ParticleSimulation::Simulated(config) => {
+                let lifetime = config.lifetime;
+                self.simulation_buffers = Some(SimulationBuffers::new(
+                    &self.context,
+                    &data.start_positions,
+                    &data.start_velocities,
+                    lifetime,
+                ));
+            }
+            ParticleSimulation::Analytic => {
+                self.simulation_buffers = None;
+            }
+        }
+
         self.instance_buffers.insert(
             "start_position".to_string(),
             InstanceBuffer::new_with_data(&self.context, &data.start_positions),
@@ -150,20 +401,8 @@ impl Particles {
             InstanceBuffer::new_with_data(&self.context, &data.start_velocities),
         );
         if let Some(texture_transforms) = &data.texture_transforms {
-            let mut instance_tex_transform1 = Vec::new();
-            let mut instance_tex_transform2 = Vec::new();
-            for texture_transform in texture_transforms.iter() {
-                instance_tex_transform1.push(vec3(
-                    texture_transform.x.x,
-                    texture_transform.y.x,
-                    texture_transform.z.x,
-                ));
-                instance_tex_transform2.push(vec3(
-                    texture_transform.x.y,
-                    texture_transform.y.y,
-                    texture_transform.z.y,
-                ));
-            }
+            let (instance_tex_transform1, instance_tex_transform2) =
+                texture_transform_rows(texture_transforms);
             self.instance_buffers.insert(
                 "tex_transform_row1".to_string(),
                 InstanceBuffer::new_with_data(&self.context, &instance_tex_transform1),
@@ -179,62 +418,244 @@ impl Particles {
                 InstanceBuffer::new_with_data(&self.context, &instance_colors),
             );
         }
+
+        // A per-instance bounding sphere, centered on the particle's seed position, used by
+        // [Culling::Frustum]/[Culling::Occlusion]. This only bounds the particle at t=0; since the
+        // analytic and simulated trajectories can carry a particle arbitrarily far from its start,
+        // callers relying on culling should keep re-`update`-ing or otherwise account for that.
+        self.bounding_spheres = data
+            .start_positions
+            .iter()
+            .map(|&center| BoundingSphere {
+                center,
+                radius: self.mesh_radius,
+            })
+            .collect();
+        self.aabb = data
+            .start_positions
+            .iter()
+            .fold(AxisAlignedBoundingBox::EMPTY, |aabb, &position| {
+                aabb.extend_with_point(position)
+            })
+            .grow(self.mesh_radius);
     }
 
-    fn vertex_shader_source(&self, fragment_shader_source: &str) -> String {
-        let use_positions = fragment_shader_source.find("in vec3 pos;").is_some();
-        let use_normals = fragment_shader_source.find("in vec3 nor;").is_some();
-        let use_tangents = fragment_shader_source.find("in vec3 tang;").is_some();
-        let use_uvs = fragment_shader_source.find("in vec2 uvs;").is_some();
-        let use_colors = fragment_shader_source.find("in vec4 col;").is_some();
-        format!(
-            "#define PARTICLES\n{}{}{}{}{}{}{}{}",
-            if use_positions {
-                "#define USE_POSITIONS\n"
-            } else {
-                ""
-            },
-            if use_normals {
-                "#define USE_NORMALS\n"
-            } else {
-                ""
-            },
-            if use_tangents {
-                if fragment_shader_source.find("in vec3 bitang;").is_none() {
-                    panic!("if the fragment shader defined 'in vec3 tang' it also needs to define 'in vec3 bitang'");
-                }
-                "#define USE_TANGENTS\n"
-            } else {
-                ""
-            },
-            if use_uvs { "#define USE_UVS\n" } else { "" },
-            if use_colors {
-                if self.instance_buffers.contains_key("instance_color")
-                    && self.vertex_buffers.contains_key("color")
-                {
-                    "#define USE_COLORS\n#define USE_VERTEX_COLORS\n#define USE_INSTANCE_COLORS\n"
-                } else if self.instance_buffers.contains_key("instance_color") {
-                    "#define USE_COLORS\n#define USE_INSTANCE_COLORS\n"
-                } else {
-                    "#define USE_COLORS\n#define USE_VERTEX_COLORS\n"
-                }
-            } else {
-                ""
-            },
-            if self.instance_buffers.contains_key("tex_transform_row1") {
-                "#define USE_INSTANCE_TEXTURE_TRANSFORMATION\n"
-            } else {
-                ""
-            },
-            include_str!("../../core/shared.frag"),
-            include_str!("shaders/mesh.vert"),
+    fn vertex_shader_source(&self, fragment_shader_source: &str) -> Result<String, ShaderError> {
+        // Parsed, rather than substring-searched, so a fragment shader that happens to mention
+        // "in vec3 pos;" in a comment can no longer spuriously enable the position attribute.
+        let declared = ShaderVariables::parse(fragment_shader_source);
+        let use_tangents = declared.inputs.contains("tang");
+        if use_tangents && !declared.inputs.contains("bitang") {
+            panic!("if the fragment shader defined 'in vec3 tang' it also needs to define 'in vec3 bitang'");
+        }
+        let use_colors = declared.inputs.contains("col");
+
+        let mut defines = HashSet::new();
+        defines.insert("PARTICLES");
+        if matches!(self.simulation, ParticleSimulation::Simulated(_)) {
+            // `mesh.vert` must, under this define, read `sim_position`/`sim_velocity` instead of
+            // evaluating the analytic trajectory from `start_position`/`start_velocity`/`time`.
+            defines.insert("USE_SIMULATED_PARTICLES");
+        }
+        if declared.inputs.contains("pos") {
+            defines.insert("USE_POSITIONS");
+        }
+        if declared.inputs.contains("nor") {
+            defines.insert("USE_NORMALS");
+        }
+        if use_tangents {
+            defines.insert("USE_TANGENTS");
+        }
+        if declared.inputs.contains("uvs") {
+            defines.insert("USE_UVS");
+        }
+        let has_instance_colors = self.instance_buffers.contains_key("instance_color");
+        let has_vertex_colors = self.vertex_buffers.contains_key("color");
+        if use_colors {
+            defines.insert("USE_COLORS");
+            if has_instance_colors {
+                defines.insert("USE_INSTANCE_COLORS");
+            }
+            if has_vertex_colors || !has_instance_colors {
+                defines.insert("USE_VERTEX_COLORS");
+            }
+        }
+        if self.instance_buffers.contains_key("tex_transform_row1") {
+            defines.insert("USE_INSTANCE_TEXTURE_TRANSFORMATION");
+        }
+
+        let mut preprocessor = ShaderPreprocessor::new();
+        preprocessor
+            .register("shared", include_str!("../../core/shared.frag"))
+            .register("mesh.vert", include_str!("shaders/mesh.vert"));
+        preprocessor.resolve("#include \"shared\"\n#include \"mesh.vert\"\n", &defines)
+    }
+
+    ///
+    /// Tests every instance's [BoundingSphere] (transformed into world space by
+    /// [Particles::transformation]) against the camera frustum and, for [Culling::Occlusion], the
+    /// Hi-Z pyramid, returning the indices of the surviving instances. Returns `None` when
+    /// [Culling::None] is set, meaning every instance should be drawn as before.
+    ///
+    /// Note: this only compacts the analytic seed attributes (gathered from the [ParticleData]
+    /// retained by [Particles::update]); a [Particles] in [ParticleSimulation::Simulated] mode
+    /// with culling enabled still draws its full `instance_count`, since its current state lives
+    /// only in the GPU-resident ping-pong buffers and isn't read back here.
+    ///
+    fn visible_indices(&self, camera: &Camera) -> Option<Vec<usize>> {
+        if matches!(self.culling, Culling::None) {
+            return None;
+        }
+        if matches!(self.simulation, ParticleSimulation::Simulated(_)) {
+            // The compacted buffers only cover the analytic seed attributes; the simulated
+            // position/velocity/age live in the full-length ping-pong buffers that aren't
+            // compacted here, so indexing a compacted seed against a full-length simulated
+            // buffer would pair up unrelated particles. Draw the full instance_count instead.
+            return None;
+        }
+        let view_projection = camera.projection() * camera.view() * self.transformation;
+        // The spheres stay in the particles' local space (the transformation is folded into
+        // `view_projection` above), so the camera position is brought into that space too.
+        let inverse_transformation = self.transformation.invert().unwrap_or(Mat4::identity());
+        let camera_position = (inverse_transformation * camera.position().extend(1.0)).truncate();
+        let hi_z = match &self.culling {
+            Culling::Occlusion { pyramid } => Some(pyramid),
+            _ => None,
+        };
+        Some(
+            self.bounding_spheres
+                .iter()
+                .enumerate()
+                .filter(|(_, sphere)| !sphere.outside_frustum(&view_projection))
+                .filter(|(_, sphere)| {
+                    hi_z.map_or(true, |pyramid| {
+                        !occluded(pyramid, sphere, camera, camera_position, &view_projection)
+                    })
+                })
+                .map(|(index, _)| index)
+                .collect(),
         )
     }
+
+    /// Gathers the analytic seed instance attributes for only the given (already culled) indices
+    /// into this [Particles]' cached compacted buffers, tightly packed to just `indices.len()`
+    /// instances, so the subsequent draw call only pays for the instances that survived culling.
+    ///
+    /// Re-fills the same [InstanceBuffer]s across frames via [InstanceBuffer::fill] rather than
+    /// allocating fresh ones with [InstanceBuffer::new_with_data] every call - the previous version
+    /// did the latter, meaning culling traded one cost (drawing occluded instances) for another
+    /// (a GPU buffer allocation per attribute, every frame) instead of actually saving work.
+    fn compact_instance_buffers(&self, indices: &[usize]) {
+        let mut buffers = self.compacted_buffers.borrow_mut();
+        fill_or_insert(
+            &self.context,
+            &mut buffers,
+            "start_position",
+            gather(&self.data.start_positions, indices),
+        );
+        fill_or_insert(
+            &self.context,
+            &mut buffers,
+            "start_velocity",
+            gather(&self.data.start_velocities, indices),
+        );
+        if let Some(colors) = &self.data.colors {
+            fill_or_insert(&self.context, &mut buffers, "instance_color", gather(colors, indices));
+        }
+        if let Some(texture_transforms) = &self.data.texture_transforms {
+            let (row1, row2) = texture_transform_rows(&gather(texture_transforms, indices));
+            fill_or_insert(&self.context, &mut buffers, "tex_transform_row1", row1);
+            fill_or_insert(&self.context, &mut buffers, "tex_transform_row2", row2);
+        }
+    }
+}
+
+/// Uploads `data` into the named entry of `buffers`, re-using the existing [InstanceBuffer] (via
+/// [InstanceBuffer::fill]) if one is already cached from a previous frame, and only allocating a new
+/// one the first time a given attribute is compacted.
+fn fill_or_insert<T: BufferDataType>(
+    context: &Context,
+    buffers: &mut HashMap<String, InstanceBuffer>,
+    name: &str,
+    data: Vec<T>,
+) {
+    match buffers.get(name) {
+        Some(buffer) => buffer.fill(&data),
+        None => {
+            buffers.insert(name.to_string(), InstanceBuffer::new_with_data(context, &data));
+        }
+    }
+}
+
+fn gather<T: Clone>(values: &[T], indices: &[usize]) -> Vec<T> {
+    indices.iter().map(|&index| values[index].clone()).collect()
+}
+
+/// Splits each per-particle texture transform into the two rows uploaded as the
+/// `tex_transform_row1`/`tex_transform_row2` instance attributes.
+fn texture_transform_rows(texture_transforms: &[Mat3]) -> (Vec<Vec3>, Vec<Vec3>) {
+    let mut row1 = Vec::new();
+    let mut row2 = Vec::new();
+    for texture_transform in texture_transforms.iter() {
+        row1.push(vec3(
+            texture_transform.x.x,
+            texture_transform.y.x,
+            texture_transform.z.x,
+        ));
+        row2.push(vec3(
+            texture_transform.x.y,
+            texture_transform.y.y,
+            texture_transform.z.y,
+        ));
+    }
+    (row1, row2)
+}
+
+/// Projects a [BoundingSphere] to screen space, selects the Hi-Z mip whose texel footprint covers
+/// the projected extent, and rejects the instance if its nearest depth is farther than the stored
+/// max depth at that location - i.e. something opaque already fully covers it.
+fn occluded(
+    pyramid: &HiZPyramid,
+    sphere: &BoundingSphere,
+    camera: &Camera,
+    camera_position: Vec3,
+    view_projection: &Mat4,
+) -> bool {
+    let center = view_projection * sphere.center.extend(1.0);
+    if center.w <= 0.0 {
+        return false;
+    }
+    let ndc = (center.x / center.w, center.y / center.w);
+
+    // The point of the sphere nearest the camera, projected separately rather than approximated by
+    // subtracting the world-space radius from the clip-space depth directly - those are different
+    // units (clip-space z against a world-space length) and mixing them doesn't even dimensionally
+    // make sense.
+    let to_center = sphere.center - camera_position;
+    let distance_to_center = to_center.magnitude();
+    let near_offset = sphere.radius.min(distance_to_center - 1.0e-4).max(0.0);
+    let nearest_point = sphere.center - to_center.normalize_to(near_offset);
+    let nearest_clip = view_projection * nearest_point.extend(1.0);
+    if nearest_clip.w <= 0.0 {
+        return false;
+    }
+    // `sample_depth` returns texture-space depth in `[0, 1]` (it reads the depth prepass texture
+    // directly), but NDC depth is in `[-1, 1]` - map it into texture space before comparing, or
+    // this rejects/accepts the wrong instances over roughly half the depth range.
+    let nearest_depth = nearest_clip.z / nearest_clip.w * 0.5 + 0.5;
+
+    // Converts the sphere's NDC-space radius into an on-screen pixel radius using the camera's
+    // actual viewport instead of a hardcoded screen size.
+    let viewport = camera.viewport();
+    let ndc_radius = sphere.radius / center.w;
+    let pixel_radius = ndc_radius * (viewport.height as f32 * 0.5);
+    let level = pyramid.level_for_pixel_radius(pixel_radius);
+    nearest_depth > pyramid.sample_depth(level, ndc)
 }
 
 impl Geometry for Particles {
     fn aabb(&self) -> AxisAlignedBoundingBox {
-        AxisAlignedBoundingBox::INFINITE
+        self.aabb
     }
 
     fn render_with_material(
@@ -248,8 +669,11 @@ impl Geometry for Particles {
                 || self.instance_buffers.contains_key("instance_color"),
             lights,
         );
+        let vertex_shader_source = self
+            .vertex_shader_source(&fragment_shader_source)
+            .unwrap_or_else(|error| panic!("failed to resolve particle vertex shader: {}", error));
         self.context.program(
-            &self.vertex_shader_source(&fragment_shader_source),
+            &vertex_shader_source,
             &fragment_shader_source,
             |program| {
                 material.use_uniforms(program, camera, lights);
@@ -273,6 +697,17 @@ impl Geometry for Particles {
                     }
                 }
 
+                let visible_indices = self.visible_indices(camera);
+                if let Some(indices) = &visible_indices {
+                    self.compact_instance_buffers(indices);
+                }
+                let compacted_buffers = self.compacted_buffers.borrow();
+                let instance_buffers = if visible_indices.is_some() {
+                    &*compacted_buffers
+                } else {
+                    &self.instance_buffers
+                };
+
                 for attribute_name in [
                     "start_position",
                     "start_velocity",
@@ -283,25 +718,45 @@ impl Geometry for Particles {
                     if program.requires_attribute(attribute_name) {
                         program.use_instance_attribute(
                             attribute_name,
-                            self.instance_buffers
+                            instance_buffers
                             .get(attribute_name).expect(&format!("the render call requires the {} instance buffer which is missing on the given geometry", attribute_name))
                         );
                     }
                 }
 
+                // Simulated particles read position/velocity/age from whichever of the two
+                // ping-pong buffers was most recently written by `Particles::animate`, instead
+                // of being evaluated analytically from the seed attributes.
+                if let Some(simulation_buffers) = &self.simulation_buffers {
+                    if matches!(self.simulation, ParticleSimulation::Simulated(_)) {
+                        for attribute_name in SimulationBuffers::ATTRIBUTES {
+                            if program.requires_attribute(attribute_name) {
+                                program.use_instance_attribute(
+                                    attribute_name,
+                                    simulation_buffers.read().get(attribute_name).unwrap(),
+                                );
+                            }
+                        }
+                    }
+                }
+
+                let instance_count = visible_indices
+                    .as_ref()
+                    .map_or(self.instance_count, |indices| indices.len() as u32);
+
                 if let Some(ref index_buffer) = self.index_buffer {
                     program.draw_elements_instanced(
                         material.render_states(),
                         camera.viewport(),
                         index_buffer,
-                        self.instance_count,
+                        instance_count,
                     )
                 } else {
                     program.draw_arrays_instanced(
                         material.render_states(),
                         camera.viewport(),
                         self.vertex_buffers.get("position").unwrap().vertex_count() as u32,
-                        self.instance_count,
+                        instance_count,
                     )
                 }
             },
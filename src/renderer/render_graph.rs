@@ -0,0 +1,464 @@
+use crate::core::*;
+use crate::frame::FrameOutput;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+
+///
+/// A handle to a resource (a color or depth texture, or the final swap-chain target) produced or
+/// consumed by a [RenderGraphNode]. Resources are declared by name; the graph resolves reads and
+/// writes of the same name to the same physical texture, allocating (and aliasing, where lifetimes
+/// don't overlap) the transient ones automatically.
+///
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ResourceHandle(String);
+
+impl ResourceHandle {
+    /// Creates a handle to the resource with the given name.
+    pub fn new(name: impl Into<String>) -> Self {
+        Self(name.into())
+    }
+
+    /// The handle naming the final swap-chain target a [RenderGraph] renders into, produced by
+    /// whichever node is terminal for that frame.
+    pub fn swap_chain() -> Self {
+        Self::new("swap_chain")
+    }
+}
+
+///
+/// Describes how a transient resource should be allocated, if and when a node that declares it as
+/// a write is actually reached during execution.
+///
+#[derive(Clone, Copy, Debug)]
+pub struct TextureSpec {
+    /// The width of the texture in texels.
+    pub width: u32,
+    /// The height of the texture in texels.
+    pub height: u32,
+    /// Whether this is a color or depth texture, and in what format.
+    pub kind: TextureKind,
+}
+
+/// The kind (and format) of a transient texture a [RenderGraph] node can declare.
+#[derive(Clone, Copy, Debug)]
+pub enum TextureKind {
+    /// An 8-bit-per-channel color texture.
+    Color,
+    /// A 16-bit-per-channel floating point color texture, used for HDR intermediate targets.
+    ColorHdr,
+    /// A depth texture.
+    Depth,
+}
+
+///
+/// A single unit of work in a [RenderGraph]: a shadow pass, a depth prepass, the opaque or
+/// transparent forward pass, a post-effect, etc. Declares the resources it reads and writes so the
+/// graph can topologically order nodes and allocate transient textures without the caller having
+/// to hand-wire framebuffers together.
+///
+pub struct RenderGraphNode {
+    name: String,
+    reads: Vec<ResourceHandle>,
+    writes: Vec<(ResourceHandle, Option<TextureSpec>)>,
+    execute: Box<dyn Fn(&RenderGraphResources)>,
+}
+
+impl RenderGraphNode {
+    /// Starts building a new node with the given name, used only for diagnostics (e.g. a cycle
+    /// error names the offending node).
+    pub fn new(name: impl Into<String>) -> RenderGraphNodeBuilder {
+        RenderGraphNodeBuilder {
+            name: name.into(),
+            reads: Vec::new(),
+            writes: Vec::new(),
+        }
+    }
+}
+
+/// Builder for a [RenderGraphNode], returned by [RenderGraphNode::new].
+pub struct RenderGraphNodeBuilder {
+    name: String,
+    reads: Vec<ResourceHandle>,
+    writes: Vec<(ResourceHandle, Option<TextureSpec>)>,
+}
+
+impl RenderGraphNodeBuilder {
+    /// Declares that this node samples the named resource, produced by an earlier node.
+    pub fn reads(mut self, resource: ResourceHandle) -> Self {
+        self.reads.push(resource);
+        self
+    }
+
+    /// Declares that this node renders into the named resource. If `spec` is given and no other
+    /// node has already allocated a physical texture for this resource, the graph allocates one
+    /// sized and formatted according to `spec` before executing the node.
+    pub fn writes(mut self, resource: ResourceHandle, spec: Option<TextureSpec>) -> Self {
+        self.writes.push((resource, spec));
+        self
+    }
+
+    /// Finishes the node, supplying the closure that executes its draw calls given the resolved
+    /// [RenderGraphResources] for this frame.
+    pub fn execute(self, execute: impl Fn(&RenderGraphResources) + 'static) -> RenderGraphNode {
+        RenderGraphNode {
+            name: self.name,
+            reads: self.reads,
+            writes: self.writes,
+            execute: Box::new(execute),
+        }
+    }
+}
+
+///
+/// The physical textures backing a [RenderGraph]'s resources for the current frame, passed to
+/// each node's execute closure so it can look up the textures behind the resource names it
+/// declared as reads/writes.
+///
+#[derive(Default)]
+pub struct RenderGraphResources {
+    textures: HashMap<String, Texture2D>,
+    depth_textures: HashMap<String, DepthTargetTexture2D>,
+    frame_output: RefCell<Option<FrameOutput>>,
+}
+
+impl RenderGraphResources {
+    /// The color texture allocated for `resource`, if any was allocated as a [TextureKind::Color]
+    /// or [TextureKind::ColorHdr] write.
+    pub fn color_texture(&self, resource: &ResourceHandle) -> Option<&Texture2D> {
+        self.textures.get(&resource.0)
+    }
+
+    /// The depth texture allocated for `resource`, if any was allocated as a [TextureKind::Depth] write.
+    pub fn depth_texture(&self, resource: &ResourceHandle) -> Option<&DepthTargetTexture2D> {
+        self.depth_textures.get(&resource.0)
+    }
+
+    ///
+    /// Reports the [FrameOutput] produced by this frame. Meant to be called by the node that
+    /// writes [ResourceHandle::swap_chain] - the terminal node - since it's the only one in a
+    /// position to know whether the window should exit or skip a buffer swap; [RenderGraph::execute]
+    /// falls back to [FrameOutput::default()] if no node calls this.
+    ///
+    pub fn set_frame_output(&self, output: FrameOutput) {
+        *self.frame_output.borrow_mut() = Some(output);
+    }
+}
+
+///
+/// A retained, declarative description of a frame's render passes, replacing the imperative style
+/// where each geometry's `render_with_material` issues draw calls directly. Callers declare
+/// [RenderGraphNode]s and the resources they read/write; [RenderGraph::execute] topologically sorts
+/// them by those dependencies, allocates/aliases transient textures, and runs them in order, with
+/// the terminal node (the one that writes [ResourceHandle::swap_chain]) producing the [FrameOutput].
+/// [RenderGraph::forward] builds the default graph, equivalent to the pre-existing forward path, so
+/// callers that don't need multi-pass features see no behavior change. The same graph can be driven
+/// from [crate::window::HeadlessContext] to render offscreen.
+///
+pub struct RenderGraph {
+    context: Context,
+    nodes: Vec<RenderGraphNode>,
+}
+
+impl RenderGraph {
+    /// Creates an empty render graph.
+    pub fn new(context: &Context) -> Self {
+        Self {
+            context: context.clone(),
+            nodes: Vec::new(),
+        }
+    }
+
+    /// Adds a node to the graph. Nodes may be added in any order - execution order is derived from
+    /// their declared resource dependencies, not insertion order.
+    pub fn add_node(&mut self, node: RenderGraphNode) -> &mut Self {
+        self.nodes.push(node);
+        self
+    }
+
+    ///
+    /// Builds the default single-pass graph equivalent to the renderer's original forward path: one
+    /// node that reads nothing and writes directly to [ResourceHandle::swap_chain], running
+    /// `render` (a closure invoking `render_with_material` on each geometry, as before). Existing
+    /// callers that construct a [RenderGraph] this way see no behavior change.
+    ///
+    pub fn forward(context: &Context, render: impl Fn(&RenderGraphResources) + 'static) -> Self {
+        let mut graph = Self::new(context);
+        graph.add_node(
+            RenderGraphNode::new("forward")
+                .writes(ResourceHandle::swap_chain(), None)
+                .execute(render),
+        );
+        graph
+    }
+
+    ///
+    /// Topologically sorts the nodes by their read/write dependencies (a node that reads a
+    /// resource runs after every node that writes it), allocates the transient textures declared by
+    /// each node's writes - aliasing a new resource onto an already-allocated texture of matching
+    /// size/format whose last reader has already run, rather than always allocating a fresh one -
+    /// and executes every node in that order. Panics if the read-after-write dependencies form a
+    /// cycle (e.g. two nodes that each read a resource the other writes) - this mirrors how a
+    /// topological sort is expected to fail on cyclic input.
+    ///
+    pub fn execute(&self) -> FrameOutput {
+        let order = self.topological_order();
+        let last_use = self.last_use_positions(&order);
+
+        let mut resources = RenderGraphResources::default();
+        let mut color_pool: Vec<(u32, u32, Format, usize, Texture2D)> = Vec::new();
+        let mut depth_pool: Vec<(u32, u32, usize, DepthTargetTexture2D)> = Vec::new();
+
+        for (position, &index) in order.iter().enumerate() {
+            let node = &self.nodes[index];
+            for (resource, spec) in &node.writes {
+                if let Some(spec) = spec {
+                    // An earlier writer of the same resource already allocated its texture.
+                    if resources.textures.contains_key(&resource.0)
+                        || resources.depth_textures.contains_key(&resource.0)
+                    {
+                        continue;
+                    }
+                    let end = last_use[resource.0.as_str()];
+                    self.allocate(
+                        &mut resources,
+                        &mut color_pool,
+                        &mut depth_pool,
+                        resource,
+                        spec,
+                        position,
+                        end,
+                    );
+                }
+            }
+            (node.execute)(&resources);
+        }
+        resources.frame_output.into_inner().unwrap_or_default()
+    }
+
+    /// For every resource written with a [TextureSpec] (i.e. every transient texture the graph
+    /// itself allocates), the last position in `order` at which it is read or written - the point
+    /// after which its physical texture is safe to alias onto a later, non-overlapping resource.
+    fn last_use_positions(&self, order: &[usize]) -> HashMap<&str, usize> {
+        let mut last_use = HashMap::new();
+        for (position, &index) in order.iter().enumerate() {
+            let node = &self.nodes[index];
+            for (resource, _) in &node.writes {
+                last_use.insert(resource.0.as_str(), position);
+            }
+            for resource in &node.reads {
+                last_use.insert(resource.0.as_str(), position);
+            }
+        }
+        last_use
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn allocate(
+        &self,
+        resources: &mut RenderGraphResources,
+        color_pool: &mut Vec<(u32, u32, Format, usize, Texture2D)>,
+        depth_pool: &mut Vec<(u32, u32, usize, DepthTargetTexture2D)>,
+        resource: &ResourceHandle,
+        spec: &TextureSpec,
+        position: usize,
+        end: usize,
+    ) {
+        match spec.kind {
+            TextureKind::Color | TextureKind::ColorHdr => {
+                let format = if matches!(spec.kind, TextureKind::ColorHdr) {
+                    Format::RGBA16F
+                } else {
+                    Format::RGBA8
+                };
+                let texture = take_aliasable(color_pool, spec.width, spec.height, format, position)
+                    .unwrap_or_else(|| Texture2D::new_empty(&self.context, spec.width, spec.height, format));
+                resources.textures.insert(resource.0.clone(), texture.clone());
+                color_pool.push((spec.width, spec.height, format, end, texture));
+            }
+            TextureKind::Depth => {
+                let texture = take_aliasable_depth(depth_pool, spec.width, spec.height, position).unwrap_or_else(|| {
+                    DepthTargetTexture2D::new(
+                        &self.context,
+                        spec.width,
+                        spec.height,
+                        Wrapping::ClampToEdge,
+                        Wrapping::ClampToEdge,
+                        DepthFormat::Depth32F,
+                    )
+                });
+                resources.depth_textures.insert(resource.0.clone(), texture.clone());
+                depth_pool.push((spec.width, spec.height, end, texture));
+            }
+        }
+    }
+
+    /// Kahn's algorithm over the read-after-write dependency edges, returning node indices in a
+    /// valid execution order. Delegates to the free function [topological_order] below so the
+    /// algorithm can be unit tested directly against hand-built nodes, without a GPU context.
+    fn topological_order(&self) -> Vec<usize> {
+        topological_order(&self.nodes)
+    }
+}
+
+/// Kahn's algorithm over the read-after-write dependency edges declared by `nodes`, returning node
+/// indices in a valid execution order. Panics if those dependencies form a cycle (e.g. two nodes
+/// that each read a resource the other writes) - this mirrors how a topological sort is expected
+/// to fail on cyclic input.
+fn topological_order(nodes: &[RenderGraphNode]) -> Vec<usize> {
+    let mut writers: HashMap<&str, usize> = HashMap::new();
+    for (index, node) in nodes.iter().enumerate() {
+        for (resource, _) in &node.writes {
+            writers.insert(&resource.0, index);
+        }
+    }
+
+    let mut incoming: Vec<HashSet<usize>> = vec![HashSet::new(); nodes.len()];
+    for (index, node) in nodes.iter().enumerate() {
+        for resource in &node.reads {
+            if let Some(&writer) = writers.get(resource.0.as_str()) {
+                if writer != index {
+                    incoming[index].insert(writer);
+                }
+            }
+        }
+    }
+
+    let mut order = Vec::with_capacity(nodes.len());
+    let mut ready: Vec<usize> = (0..nodes.len()).filter(|index| incoming[*index].is_empty()).collect();
+    let mut visited = vec![false; nodes.len()];
+    while let Some(index) = ready.pop() {
+        if visited[index] {
+            continue;
+        }
+        visited[index] = true;
+        order.push(index);
+        for (other, deps) in incoming.iter_mut().enumerate() {
+            if deps.remove(&index) && deps.is_empty() && !visited[other] {
+                ready.push(other);
+            }
+        }
+    }
+    assert_eq!(
+        order.len(),
+        nodes.len(),
+        "render graph has a cycle among nodes {:?}",
+        (0..nodes.len())
+            .filter(|i| !visited[*i])
+            .map(|i| nodes[i].name.clone())
+            .collect::<Vec<_>>()
+    );
+    order
+}
+
+/// Finds and removes the first pool entry matching `width`/`height`/`format` whose last use has
+/// already passed (`end < position`), so its texture can be reused by a new, non-overlapping
+/// resource instead of allocating a fresh one. Entries that are still in use are left in the pool.
+fn take_aliasable(
+    pool: &mut Vec<(u32, u32, Format, usize, Texture2D)>,
+    width: u32,
+    height: u32,
+    format: Format,
+    position: usize,
+) -> Option<Texture2D> {
+    let keys: Vec<((u32, u32, Format), usize)> = pool.iter().map(|(w, h, f, end, _)| ((*w, *h, *f), *end)).collect();
+    let index = find_aliasable_index(&keys, &(width, height, format), position)?;
+    Some(pool.remove(index).4)
+}
+
+/// The depth-texture equivalent of [take_aliasable].
+fn take_aliasable_depth(
+    pool: &mut Vec<(u32, u32, usize, DepthTargetTexture2D)>,
+    width: u32,
+    height: u32,
+    position: usize,
+) -> Option<DepthTargetTexture2D> {
+    let keys: Vec<((u32, u32), usize)> = pool.iter().map(|(w, h, end, _)| ((*w, *h), *end)).collect();
+    let index = find_aliasable_index(&keys, &(width, height), position)?;
+    Some(pool.remove(index).3)
+}
+
+/// The matching logic behind [take_aliasable]/[take_aliasable_depth], pulled out of the texture
+/// pool itself so it can be unit tested without a GPU context: the first entry whose key matches
+/// and whose last use (`end`) has already passed (`end < position`).
+fn find_aliasable_index<K: PartialEq>(entries: &[(K, usize)], key: &K, position: usize) -> Option<usize> {
+    entries.iter().position(|(k, end)| k == key && *end < position)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(name: &str, reads: &[&str], writes: &[&str]) -> RenderGraphNode {
+        let mut builder = RenderGraphNode::new(name);
+        for resource in reads {
+            builder = builder.reads(ResourceHandle::new(*resource));
+        }
+        for resource in writes {
+            builder = builder.writes(ResourceHandle::new(*resource), None);
+        }
+        builder.execute(|_resources| {})
+    }
+
+    #[test]
+    fn independent_nodes_can_run_in_any_order() {
+        let nodes = vec![node("a", &[], &["a_out"]), node("b", &[], &["b_out"])];
+        let order = topological_order(&nodes);
+        assert_eq!(order.len(), 2);
+        assert!(order.contains(&0) && order.contains(&1));
+    }
+
+    #[test]
+    fn reader_runs_after_its_writer() {
+        let nodes = vec![
+            node("consumer", &["shared"], &["swap_chain"]),
+            node("producer", &[], &["shared"]),
+        ];
+        let order = topological_order(&nodes);
+        let producer_position = order.iter().position(|&i| i == 1).unwrap();
+        let consumer_position = order.iter().position(|&i| i == 0).unwrap();
+        assert!(producer_position < consumer_position);
+    }
+
+    #[test]
+    fn chain_of_three_nodes_runs_in_dependency_order() {
+        let nodes = vec![
+            node("depth_prepass", &[], &["depth"]),
+            node("opaque", &["depth"], &["color"]),
+            node("post_effect", &["color"], &["swap_chain"]),
+        ];
+        let order = topological_order(&nodes);
+        assert_eq!(order, vec![0, 1, 2]);
+    }
+
+    #[test]
+    #[should_panic(expected = "render graph has a cycle")]
+    fn cycle_between_two_writers_panics() {
+        // Both nodes write a resource the other reads, so neither can be ordered before the other.
+        let nodes = vec![node("a", &["b_out"], &["a_out"]), node("b", &["a_out"], &["b_out"])];
+        topological_order(&nodes);
+    }
+
+    #[test]
+    fn find_aliasable_index_reuses_matching_entry_whose_last_use_has_passed() {
+        let entries = vec![(("key", 1u32), 2usize)];
+        assert_eq!(find_aliasable_index(&entries, &("key", 1), 3), Some(0));
+    }
+
+    #[test]
+    fn find_aliasable_index_rejects_entry_still_in_use() {
+        let entries = vec![(("key", 1u32), 5usize)];
+        assert_eq!(find_aliasable_index(&entries, &("key", 1), 3), None);
+    }
+
+    #[test]
+    fn find_aliasable_index_rejects_mismatched_key() {
+        let entries = vec![(("key", 1u32), 0usize)];
+        assert_eq!(find_aliasable_index(&entries, &("key", 2), 3), None);
+    }
+
+    #[test]
+    fn find_aliasable_index_picks_first_eligible_match() {
+        let entries = vec![(("key", 1u32), 0usize), (("key", 1u32), 1usize)];
+        assert_eq!(find_aliasable_index(&entries, &("key", 1), 2), Some(0));
+    }
+}
@@ -0,0 +1,403 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+///
+/// A registry of named GLSL source fragments that can reference each other through
+/// `#include "name"` directives, resolved recursively with cycle detection and per-include
+/// deduplication (a module included from two different places is only pasted once). Geometries
+/// and materials register the modules they depend on and declare the variables they need through
+/// this preprocessor instead of concatenating whole files and substring-searching for attributes,
+/// which is what [crate::renderer::geometry::Particles] used to do.
+///
+#[derive(Default)]
+pub struct ShaderPreprocessor<'a> {
+    modules: HashMap<&'a str, &'a str>,
+}
+
+impl<'a> ShaderPreprocessor<'a> {
+    /// Creates an empty preprocessor with no registered modules.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers `source` under `name`, making it available to `#include "name"` directives.
+    /// Re-registering the same name overwrites the previous source.
+    pub fn register(&mut self, name: &'a str, source: &'a str) -> &mut Self {
+        self.modules.insert(name, source);
+        self
+    }
+
+    ///
+    /// Resolves all `#include "name"` directives in `source`, recursively, replacing each
+    /// directive with the named module's (already resolved) source. A module is only pasted once
+    /// even if included from multiple places in the dependency tree; subsequent includes of an
+    /// already-resolved module are dropped. `#define NAME` and `#ifdef NAME ... #endif` blocks are
+    /// evaluated against `defines` so callers can declare dependencies explicitly instead of
+    /// pre-concatenating optional blocks with string formatting.
+    ///
+    pub fn resolve(&self, source: &str, defines: &HashSet<&str>) -> Result<String, ShaderError> {
+        let mut included = HashSet::new();
+        let mut stack = Vec::new();
+        let resolved = self.resolve_includes(source, &mut included, &mut stack)?;
+        Ok(Self::resolve_conditionals(&resolved, defines))
+    }
+
+    fn resolve_includes(
+        &self,
+        source: &str,
+        included: &mut HashSet<&'a str>,
+        stack: &mut Vec<&'a str>,
+    ) -> Result<String, ShaderError> {
+        let mut output = String::with_capacity(source.len());
+        for (line_number, line) in source.lines().enumerate() {
+            if let Some(name) = parse_include(line) {
+                let Some(&module_source) = self.modules.get(name) else {
+                    return Err(ShaderError::UnknownModule {
+                        name: name.to_string(),
+                        line: line_number + 1,
+                    });
+                };
+                if stack.contains(&name) {
+                    return Err(ShaderError::IncludeCycle {
+                        name: name.to_string(),
+                        line: line_number + 1,
+                    });
+                }
+                if included.insert(name) {
+                    stack.push(name);
+                    let resolved = self.resolve_includes(module_source, included, stack)?;
+                    stack.pop();
+                    output.push_str(&resolved);
+                    output.push('\n');
+                }
+            } else {
+                output.push_str(line);
+                output.push('\n');
+            }
+        }
+        Ok(output)
+    }
+
+    /// Strips `#ifdef`/`#ifndef`/`#if defined(...)` ... [`#elif ...` ...] [`#else` ...] `#endif`
+    /// blocks according to whether the condition holds against `defines`, supporting arbitrary
+    /// nesting. A bare `#define NAME` (no value) is consumed and extends the set a later
+    /// conditional in the same source is evaluated against, the same way a real preprocessor's
+    /// `#define`/`#ifdef` interact - but a value macro like `#define SHADOW_FILTER_PCF 16` is left
+    /// in the output untouched, since this preprocessor only understands flag-style conditionals
+    /// and the GLSL compiler still needs to see the value macro itself to resolve code that uses
+    /// it as a constant.
+    fn resolve_conditionals(source: &str, defines: &HashSet<&str>) -> String {
+        /// One nested conditional block: whether its current branch should be emitted, whether
+        /// any of its branches has matched yet (so `#else`/`#elif` only flip a block from
+        /// inactive to active once), and whether the block it's nested inside is itself active.
+        struct Frame {
+            active: bool,
+            matched: bool,
+            parent_active: bool,
+        }
+        fn currently_active(stack: &[Frame]) -> bool {
+            stack.last().map_or(true, |frame| frame.active)
+        }
+
+        /// Evaluates a `#if`/`#elif` expression built out of `defined(NAME)`/`!defined(NAME)`
+        /// atoms combined with `&&`/`||` - the only forms `mesh.vert` actually uses. `&&` binds
+        /// tighter than `||`, matching C/GLSL precedence.
+        fn eval_condition(expr: &str, defines: &HashSet<String>) -> bool {
+            fn eval_atom(atom: &str, defines: &HashSet<String>) -> bool {
+                let (negate, rest) = match atom.strip_prefix('!') {
+                    Some(rest) => (true, rest.trim()),
+                    None => (false, atom),
+                };
+                let name = rest
+                    .strip_prefix("defined(")
+                    .and_then(|s| s.strip_suffix(')'))
+                    .unwrap_or(rest)
+                    .trim();
+                defines.contains(name) != negate
+            }
+            expr.split("||")
+                .any(|conjunction| conjunction.split("&&").all(|atom| eval_atom(atom.trim(), defines)))
+        }
+
+        let mut local_defines: HashSet<String> = defines.iter().map(|s| s.to_string()).collect();
+        let mut stack: Vec<Frame> = Vec::new();
+        let mut output = String::with_capacity(source.len());
+
+        for line in source.lines() {
+            let trimmed = line.trim();
+            if let Some(name) = trimmed.strip_prefix("#ifdef ") {
+                let parent_active = currently_active(&stack);
+                let matched = parent_active && local_defines.contains(name.trim());
+                stack.push(Frame { active: matched, matched, parent_active });
+                continue;
+            }
+            if let Some(name) = trimmed.strip_prefix("#ifndef ") {
+                let parent_active = currently_active(&stack);
+                let matched = parent_active && !local_defines.contains(name.trim());
+                stack.push(Frame { active: matched, matched, parent_active });
+                continue;
+            }
+            if let Some(expr) = trimmed.strip_prefix("#if ") {
+                let parent_active = currently_active(&stack);
+                let matched = parent_active && eval_condition(expr.trim(), &local_defines);
+                stack.push(Frame { active: matched, matched, parent_active });
+                continue;
+            }
+            if let Some(expr) = trimmed.strip_prefix("#elif ") {
+                if let Some(frame) = stack.last_mut() {
+                    let condition = eval_condition(expr.trim(), &local_defines);
+                    frame.active = frame.parent_active && !frame.matched && condition;
+                    frame.matched = frame.matched || frame.active;
+                }
+                continue;
+            }
+            if trimmed == "#else" {
+                if let Some(frame) = stack.last_mut() {
+                    frame.active = frame.parent_active && !frame.matched;
+                    frame.matched = frame.matched || frame.active;
+                }
+                continue;
+            }
+            if trimmed == "#endif" {
+                stack.pop();
+                continue;
+            }
+            if let Some(rest) = trimmed.strip_prefix("#define ") {
+                // A bare `#define NAME` is one of this preprocessor's own flags, consumed here;
+                // `#define NAME value` is a GLSL value macro meant for the compiler, so it falls
+                // through to the `output.push_str` below instead of being stripped.
+                if !rest.trim().contains(char::is_whitespace) {
+                    if currently_active(&stack) {
+                        local_defines.insert(rest.trim().to_string());
+                    }
+                    continue;
+                }
+            }
+            if currently_active(&stack) {
+                output.push_str(line);
+                output.push('\n');
+            }
+        }
+        output
+    }
+}
+
+fn parse_include(line: &str) -> Option<&str> {
+    let rest = line.trim().strip_prefix("#include")?;
+    let rest = rest.trim();
+    let rest = rest.strip_prefix('"')?;
+    rest.strip_suffix('"')
+}
+
+///
+/// The `in`/`out` variables a resolved shader source declares at global scope, used in place of
+/// the brittle `fragment_shader_source.find("in vec3 pos;")` substring search to decide which
+/// vertex attributes a geometry needs to supply.
+///
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ShaderVariables {
+    /// Names of variables declared `in <type> <name>;` at global scope.
+    pub inputs: HashSet<String>,
+    /// Names of variables declared `out <type> <name>;` at global scope.
+    pub outputs: HashSet<String>,
+}
+
+impl ShaderVariables {
+    /// Parses the `in`/`out` declarations out of a resolved (include-free) shader source.
+    pub fn parse(source: &str) -> Self {
+        let mut variables = Self::default();
+        for line in source.lines() {
+            let trimmed = line.trim();
+            if let Some(rest) = trimmed.strip_prefix("in ") {
+                if let Some(name) = declared_name(rest) {
+                    variables.inputs.insert(name);
+                }
+            } else if let Some(rest) = trimmed.strip_prefix("out ") {
+                if let Some(name) = declared_name(rest) {
+                    variables.outputs.insert(name);
+                }
+            }
+        }
+        variables
+    }
+}
+
+/// Extracts `name` out of a `<type> name;` declaration tail.
+fn declared_name(type_and_name: &str) -> Option<String> {
+    let without_semicolon = type_and_name.trim().trim_end_matches(';');
+    without_semicolon.split_whitespace().nth(1).map(str::to_string)
+}
+
+///
+/// An error produced while resolving `#include` directives, surfaced instead of a bare
+/// `panic!`/`expect` so callers can report which module and line triggered the failure.
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ShaderError {
+    /// An `#include "name"` directive referenced a module that was never registered.
+    UnknownModule {
+        /// The unresolved module name.
+        name: String,
+        /// The 1-indexed line, within the including source, of the offending directive.
+        line: usize,
+    },
+    /// An `#include` chain included a module that is already an ancestor of itself.
+    IncludeCycle {
+        /// The module name that would have been included cyclically.
+        name: String,
+        /// The 1-indexed line, within the including source, of the offending directive.
+        line: usize,
+    },
+}
+
+impl fmt::Display for ShaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShaderError::UnknownModule { name, line } => write!(
+                f,
+                "shader include error at line {}: no module named \"{}\" is registered",
+                line, name
+            ),
+            ShaderError::IncludeCycle { name, line } => write!(
+                f,
+                "shader include error at line {}: \"{}\" includes itself, directly or indirectly",
+                line, name
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ShaderError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn defines<'a>(names: impl IntoIterator<Item = &'a str>) -> HashSet<&'a str> {
+        names.into_iter().collect()
+    }
+
+    #[test]
+    fn ifdef_keeps_branch_when_defined() {
+        let source = "a\n#ifdef FOO\nb\n#endif\nc";
+        assert_eq!(ShaderPreprocessor::resolve_conditionals(source, &defines(["FOO"])), "a\nb\nc\n");
+    }
+
+    #[test]
+    fn ifdef_drops_branch_when_not_defined() {
+        let source = "a\n#ifdef FOO\nb\n#endif\nc";
+        assert_eq!(ShaderPreprocessor::resolve_conditionals(source, &defines([])), "a\nc\n");
+    }
+
+    #[test]
+    fn ifndef_is_the_inverse_of_ifdef() {
+        let source = "#ifndef FOO\na\n#endif";
+        assert_eq!(ShaderPreprocessor::resolve_conditionals(source, &defines([])), "a\n");
+        assert_eq!(ShaderPreprocessor::resolve_conditionals(source, &defines(["FOO"])), "");
+    }
+
+    #[test]
+    fn else_branch_taken_when_ifdef_condition_is_false() {
+        let source = "#ifdef FOO\na\n#else\nb\n#endif";
+        assert_eq!(ShaderPreprocessor::resolve_conditionals(source, &defines([])), "b\n");
+        assert_eq!(ShaderPreprocessor::resolve_conditionals(source, &defines(["FOO"])), "a\n");
+    }
+
+    #[test]
+    fn nested_conditionals_resolve_independently() {
+        let source = "#ifdef OUTER\nbefore\n#ifdef INNER\ninner\n#else\nnot-inner\n#endif\nafter\n#endif";
+        assert_eq!(
+            ShaderPreprocessor::resolve_conditionals(source, &defines(["OUTER", "INNER"])),
+            "before\ninner\nafter\n"
+        );
+        assert_eq!(
+            ShaderPreprocessor::resolve_conditionals(source, &defines(["OUTER"])),
+            "before\nnot-inner\nafter\n"
+        );
+        assert_eq!(ShaderPreprocessor::resolve_conditionals(source, &defines([])), "");
+    }
+
+    #[test]
+    fn inactive_nested_block_is_skipped_even_if_its_own_condition_would_match() {
+        // INNER is defined, but OUTER is not, so the whole block - including the nested #ifdef
+        // INNER branch - must stay inactive regardless of INNER's own truth value.
+        let source = "#ifdef OUTER\n#ifdef INNER\nvisible\n#endif\n#endif\nafter";
+        assert_eq!(ShaderPreprocessor::resolve_conditionals(source, &defines(["INNER"])), "after\n");
+    }
+
+    #[test]
+    fn bare_define_extends_later_ifdef_in_the_same_source() {
+        let source = "#define FOO\n#ifdef FOO\na\n#endif";
+        assert_eq!(ShaderPreprocessor::resolve_conditionals(source, &defines([])), "a\n");
+    }
+
+    #[test]
+    fn value_macro_is_passed_through_to_the_glsl_compiler() {
+        // Unlike a bare `#define FOO`, a value macro isn't one of this preprocessor's own flags -
+        // it must survive in the output for the GLSL compiler to see.
+        let source = "#define SHADOW_FILTER_PCF 16\nconst int samples = SHADOW_FILTER_PCF;";
+        assert_eq!(
+            ShaderPreprocessor::resolve_conditionals(source, &defines([])),
+            "#define SHADOW_FILTER_PCF 16\nconst int samples = SHADOW_FILTER_PCF;\n"
+        );
+    }
+
+    #[test]
+    fn value_macro_inside_inactive_block_is_still_dropped() {
+        let source = "#ifdef FOO\n#define BAR 1\n#endif\nafter";
+        assert_eq!(ShaderPreprocessor::resolve_conditionals(source, &defines([])), "after\n");
+    }
+
+    #[test]
+    fn if_defined_behaves_like_ifdef() {
+        let source = "#if defined(FOO)\na\n#endif\nb";
+        assert_eq!(ShaderPreprocessor::resolve_conditionals(source, &defines(["FOO"])), "a\nb\n");
+        assert_eq!(ShaderPreprocessor::resolve_conditionals(source, &defines([])), "b\n");
+    }
+
+    #[test]
+    fn if_defined_or_takes_either_branch() {
+        let source = "#if defined(FOO) || defined(BAR)\na\n#endif";
+        assert_eq!(ShaderPreprocessor::resolve_conditionals(source, &defines(["FOO"])), "a\n");
+        assert_eq!(ShaderPreprocessor::resolve_conditionals(source, &defines(["BAR"])), "a\n");
+        assert_eq!(ShaderPreprocessor::resolve_conditionals(source, &defines([])), "");
+    }
+
+    #[test]
+    fn if_defined_and_requires_both() {
+        let source = "#if defined(FOO) && defined(BAR)\na\n#endif";
+        assert_eq!(ShaderPreprocessor::resolve_conditionals(source, &defines(["FOO"])), "");
+        assert_eq!(
+            ShaderPreprocessor::resolve_conditionals(source, &defines(["FOO", "BAR"])),
+            "a\n"
+        );
+    }
+
+    #[test]
+    fn elif_chain_picks_the_first_matching_branch() {
+        let source = "#if defined(FOO) && defined(BAR)\nboth\n#elif defined(FOO)\nfoo-only\n#else\nneither\n#endif";
+        assert_eq!(
+            ShaderPreprocessor::resolve_conditionals(source, &defines(["FOO", "BAR"])),
+            "both\n"
+        );
+        assert_eq!(ShaderPreprocessor::resolve_conditionals(source, &defines(["FOO"])), "foo-only\n");
+        assert_eq!(ShaderPreprocessor::resolve_conditionals(source, &defines([])), "neither\n");
+    }
+
+    #[test]
+    fn endif_only_closes_the_frame_its_own_if_pushed() {
+        // Regression test: every opening directive (`#ifdef`/`#ifndef`/`#if`) must push exactly one
+        // frame so a later `#endif` pops the block it actually belongs to, rather than silently
+        // falling through unrecognized and corrupting an enclosing, unrelated block.
+        let source = "#ifdef USE_COLORS\n#if defined(FOO)\na\n#else\nb\n#endif\nafter\n#endif\ntail";
+        assert_eq!(
+            ShaderPreprocessor::resolve_conditionals(source, &defines(["USE_COLORS", "FOO"])),
+            "a\nafter\ntail\n"
+        );
+        assert_eq!(
+            ShaderPreprocessor::resolve_conditionals(source, &defines(["USE_COLORS"])),
+            "b\nafter\ntail\n"
+        );
+        assert_eq!(ShaderPreprocessor::resolve_conditionals(source, &defines([])), "tail\n");
+    }
+}
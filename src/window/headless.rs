@@ -1,3 +1,4 @@
+use crate::window::render_target::{RenderTarget, RenderTargetOptions};
 use crate::{Context, WindowError};
 use glutin_029::{
     event_loop::EventLoop, ContextBuilder, ContextCurrentState, CreationError, NotCurrent,
@@ -41,6 +42,19 @@ impl std::ops::Deref for HeadlessContext {
     }
 }
 
+impl HeadlessContext {
+    ///
+    /// Creates a [RenderTarget] using this context, with the given options, that a camera can
+    /// render the scene into and whose pixels can then be read back with
+    /// [RenderTarget::read_color]/[RenderTarget::read_depth] - the piece that was missing to make
+    /// the headless path useful for automated visual regression testing, since there is no window
+    /// to present the result in otherwise.
+    ///
+    pub fn render_target(&self, options: RenderTargetOptions) -> RenderTarget {
+        RenderTarget::new(&self.context, options)
+    }
+}
+
 /*#[cfg(target_os = "linux")]
 fn build_context_surfaceless<T1: ContextCurrentState>(
     cb: ContextBuilder<T1>,
@@ -0,0 +1,216 @@
+use crate::core::*;
+
+///
+/// An offscreen render target: a color texture (optionally HDR/float) plus an optional depth
+/// texture, that a [Camera] can render the scene into and whose pixels can then be read back into
+/// a CPU buffer. This is what makes [crate::window::HeadlessContext] useful on its own - without
+/// it there is a context but nowhere to render into and no way to get the result out, which is the
+/// whole point of running headless (CI image tests, thumbnail generation, server-side rendering).
+///
+pub struct RenderTarget {
+    context: Context,
+    color: ColorTarget,
+    depth: Option<DepthTargetTexture2D>,
+    resolved_color: Option<Texture2D>,
+}
+
+/// The color texture backing a [RenderTarget], either a regular (possibly HDR) texture or a
+/// multisampled one that must be resolved before it can be sampled or read back.
+enum ColorTarget {
+    Texture2D(Texture2D),
+    Multisampled(ColorTargetTexture2DMultisample),
+}
+
+///
+/// Configuration for [RenderTarget::new].
+///
+#[derive(Clone, Copy, Debug)]
+pub struct RenderTargetOptions {
+    /// The width, in texels, of the target.
+    pub width: u32,
+    /// The height, in texels, of the target.
+    pub height: u32,
+    /// When `true`, the color texture stores 16-bit-per-channel floats instead of 8-bit-per-channel
+    /// integers, so values outside `[0, 1]` (e.g. from additive blending or emissive surfaces)
+    /// aren't clamped before a post-effect pass gets to tone map them.
+    pub hdr: bool,
+    /// Whether to allocate a depth texture alongside the color texture, needed for most 3D scenes
+    /// unless depth testing happens in an earlier pass this target reads from.
+    pub depth: bool,
+    /// The number of samples per texel for multisample anti-aliasing, or `1` to disable MSAA. A
+    /// multisampled target must be resolved with [RenderTarget::resolve] before its color can be
+    /// sampled by a later pass or read back with [RenderTarget::read_color].
+    pub samples: u32,
+}
+
+impl Default for RenderTargetOptions {
+    fn default() -> Self {
+        Self {
+            width: 256,
+            height: 256,
+            hdr: false,
+            depth: true,
+            samples: 1,
+        }
+    }
+}
+
+impl RenderTarget {
+    ///
+    /// Creates a new offscreen render target with the given options. Usable from any [Context],
+    /// including the one behind [crate::window::HeadlessContext], since allocating and rendering
+    /// into a texture never requires an on-screen surface.
+    ///
+    pub fn new(context: &Context, options: RenderTargetOptions) -> Self {
+        let format = if options.hdr {
+            Format::RGBA16F
+        } else {
+            Format::RGBA8
+        };
+        let color = if options.samples > 1 {
+            ColorTarget::Multisampled(ColorTargetTexture2DMultisample::new(
+                context,
+                options.width,
+                options.height,
+                options.samples,
+                format,
+            ))
+        } else {
+            ColorTarget::Texture2D(Texture2D::new_empty(
+                context,
+                options.width,
+                options.height,
+                format,
+            ))
+        };
+        let depth = options.depth.then(|| {
+            DepthTargetTexture2D::new(
+                context,
+                options.width,
+                options.height,
+                Wrapping::ClampToEdge,
+                Wrapping::ClampToEdge,
+                DepthFormat::Depth32F,
+            )
+        });
+        Self {
+            context: context.clone(),
+            color,
+            depth,
+            resolved_color: None,
+        }
+    }
+
+    ///
+    /// Renders `render` (typically a closure invoking `render_with_material` for each object in the
+    /// scene, as seen from the camera the closure renders with) into this target's color and, if present, depth texture,
+    /// clearing both first.
+    ///
+    pub fn render(&self, clear_color: Color, render: impl FnOnce()) {
+        let depth = self.depth.as_ref();
+        match &self.color {
+            ColorTarget::Texture2D(texture) => {
+                RenderTargetBuilder::new(&self.context, texture, depth).clear_and_render(clear_color, render)
+            }
+            ColorTarget::Multisampled(texture) => {
+                RenderTargetBuilder::new_multisample(&self.context, texture, depth)
+                    .clear_and_render(clear_color, render)
+            }
+        }
+    }
+
+    ///
+    /// Resolves a multisampled color texture into a plain [Texture2D] that can be sampled by a
+    /// later pass or read back with [RenderTarget::read_color]. A no-op (and unnecessary to call)
+    /// on a target that wasn't created with `samples > 1`.
+    ///
+    pub fn resolve(&mut self) {
+        if let ColorTarget::Multisampled(multisampled) = &self.color {
+            let resolved = self
+                .resolved_color
+                .get_or_insert_with(|| Texture2D::new_empty(&self.context, multisampled.width(), multisampled.height(), multisampled.format()));
+            multisampled.resolve_into(resolved);
+        }
+    }
+
+    ///
+    /// The color texture a later pass should sample from - the resolved texture if this target is
+    /// multisampled and [RenderTarget::resolve] has been called, otherwise the target's own color
+    /// texture. Lets callers chain targets so post-effects can sample a previous pass.
+    ///
+    pub fn color_texture(&self) -> &Texture2D {
+        match (&self.color, &self.resolved_color) {
+            (_, Some(resolved)) => resolved,
+            (ColorTarget::Texture2D(texture), None) => texture,
+            (ColorTarget::Multisampled(_), None) => {
+                panic!("a multisampled render target must be resolved with `RenderTarget::resolve` before its color texture can be sampled")
+            }
+        }
+    }
+
+    /// The depth texture this target renders into, if it was created with `depth: true`.
+    pub fn depth_texture(&self) -> Option<&DepthTargetTexture2D> {
+        self.depth.as_ref()
+    }
+
+    ///
+    /// Reads the color texture back into a CPU buffer of 8-bit RGBA pixels (or, for an HDR
+    /// target, 16-bit-per-channel float pixels packed the same way), row-major starting at the
+    /// bottom-left texel. Suitable for encoding directly to PNG.
+    ///
+    pub fn read_color(&self) -> Vec<u8> {
+        self.color_texture().read()
+    }
+
+    ///
+    /// Reads the depth texture back into a CPU buffer of `f32` depth values, row-major starting at
+    /// the bottom-left texel, suitable for encoding to a format like HDR or for depth-based visual
+    /// regression comparisons. Panics if this target has no depth texture.
+    ///
+    pub fn read_depth(&self) -> Vec<f32> {
+        self.depth
+            .as_ref()
+            .expect("this render target has no depth texture - create it with `depth: true`")
+            .read()
+    }
+}
+
+/// Binds either a regular or a multisampled color+depth pair as the current render target for the
+/// duration of `clear_and_render`. Kept private since it only exists to share the clear+bind dance
+/// between the two [ColorTarget] variants.
+///
+/// Note this does *not* restore whatever target was bound before `clear_and_render` - there is no
+/// `Drop` impl, and the context has no way to query the binding that was active beforehand. A
+/// caller chaining renders into more than one target (e.g. sampling this target's color texture
+/// from a later pass) needs to explicitly bind that later target itself; don't rely on this type to
+/// put things back the way they were.
+struct RenderTargetBuilder<'a> {
+    context: &'a Context,
+    bind: Box<dyn Fn() + 'a>,
+}
+
+impl<'a> RenderTargetBuilder<'a> {
+    fn new(context: &'a Context, color: &'a Texture2D, depth: Option<&'a DepthTargetTexture2D>) -> Self {
+        Self {
+            context,
+            bind: Box::new(move || color.bind_as_color_target(depth)),
+        }
+    }
+
+    fn new_multisample(
+        context: &'a Context,
+        color: &'a ColorTargetTexture2DMultisample,
+        depth: Option<&'a DepthTargetTexture2D>,
+    ) -> Self {
+        Self {
+            context,
+            bind: Box::new(move || color.bind_as_color_target(depth)),
+        }
+    }
+
+    fn clear_and_render(&self, clear_color: Color, render: impl FnOnce()) {
+        (self.bind)();
+        self.context.clear_color_and_depth(clear_color);
+        render();
+    }
+}